@@ -43,6 +43,9 @@ impl<T: Transport> Eth<T> {
     }
 
     /// Call a constant method of contract without changing the state of the blockchain.
+    ///
+    /// Track a [`Provenance`](crate::transports::ic_http_client::Provenance) in `options` to tell
+    /// whether the result went through consensus before relying on it.
     pub fn call(&self, req: CallRequest, block: Option<BlockId>, options: CallOptions) -> CallFuture<Bytes, T::Out> {
         let req = helpers::serialize(&req);
         let block = helpers::serialize(&block.unwrap_or_else(|| BlockNumber::Latest.into()));
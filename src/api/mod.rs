@@ -28,13 +28,13 @@ pub use self::{
 
 use crate::{
     confirm, error,
-    transports::ic_http_client::CallOptions,
-    types::{Bytes, TransactionReceipt, TransactionRequest, U64},
+    transports::{ic_http_client::CallOptions, Head, Intercepted, Interceptor, Pinned},
+    types::{BlockId, BlockNumber, Bytes, TransactionReceipt, TransactionRequest, U64},
     DuplexTransport, Error, RequestId, Transport,
 };
 use futures::Future;
 use jsonrpc_core::types::Call;
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 /// Common API for all namespaces
 pub trait Namespace<T: Transport>: Clone {
@@ -62,6 +62,52 @@ impl<T: Transport> Web3<T> {
         &self.transport
     }
 
+    /// Runs every call of this `Web3` through `interceptors`, in order.
+    pub fn with_interceptors<I>(self, interceptors: I) -> Web3<Intercepted<T>>
+    where
+        I: IntoIterator<Item = Arc<dyn Interceptor>>,
+        T::Out: Send + 'static,
+    {
+        let mut transport = Intercepted::new(self.transport);
+        for interceptor in interceptors {
+            transport = transport.with_arc(interceptor);
+        }
+        Web3::new(transport)
+    }
+
+    /// Reads state at `block` with every state-reading method, including `Contract::query`.
+    pub fn at_block(&self, block: impl Into<U64>) -> Web3<Pinned<T>>
+    where
+        T::Out: Send + 'static,
+    {
+        Web3::new(Pinned::new(self.transport.clone(), block.into()))
+    }
+
+    /// Resolves `head` once and reads state at it, so that reads within one update are consistent.
+    pub async fn pinned(&self, head: Head, options: CallOptions) -> error::Result<Web3<Pinned<T>>>
+    where
+        T::Out: Send + 'static,
+    {
+        let block = match head {
+            Head::Confirmations(confirmations) => {
+                let latest = self.eth().block_number(options).await?;
+                latest.saturating_sub(confirmations.into())
+            }
+            Head::Safe | Head::Finalized => {
+                let tag = match head {
+                    Head::Safe => BlockNumber::Safe,
+                    _ => BlockNumber::Finalized,
+                };
+                self.eth()
+                    .block(BlockId::Number(tag), options)
+                    .await?
+                    .and_then(|block| block.number)
+                    .ok_or_else(|| Error::InvalidResponse(format!("no {:?} block", head)))?
+            }
+        };
+        Ok(self.at_block(block))
+    }
+
     /// set the max response bytes
     pub fn set_max_response_bytes(&mut self, bytes: u64) {
         self.transport.set_max_response_bytes(bytes)
@@ -5,8 +5,7 @@ use crate::{
     confirm,
     contract::tokens::{Detokenize, Tokenize},
     futures::Future,
-    ic::KeyInfo,
-    transports::ic_http_client::CallOptions,
+    transports::ic_http_client::{CallOptions, Provenance, Replication},
     types::{
         AccessList, Address, BlockId, Bytes, CallRequest, FilterBuilder, TransactionCondition, TransactionParameters,
         TransactionReceipt, TransactionRequest, H256, U256, U64,
@@ -263,6 +262,10 @@ impl<T: Transport> Contract<T> {
     }
 
     /// Call constant function
+    ///
+    /// Refuses results obtained with a non-replicated outcall, which didn't go through consensus,
+    /// whether the call options or a transport asked for it, see [`Contract::query_non_replicated`].
+    /// Call options asking for one are refused without an outcall.
     pub fn query<R, A, B, P>(
         &self,
         func: &str,
@@ -277,13 +280,67 @@ impl<T: Transport> Contract<T> {
         B: Into<Option<BlockId>>,
         P: Tokenize,
     {
-        let result = self
-            .abi
-            .function(func)
+        self.query_with(func, params, from, options, block, true)
+    }
+
+    /// Call constant function with a single-node outcall, for a fraction of the cycles.
+    ///
+    /// The result is only as trustworthy as the node which made the outcall and its RPC provider,
+    /// so it must not feed decisions such as signing or transferring funds.
+    pub fn query_non_replicated<R, A, B, P>(
+        &self,
+        func: &str,
+        params: P,
+        from: A,
+        mut options: Options,
+        block: B,
+    ) -> impl Future<Output = Result<R>> + '_
+    where
+        R: Detokenize,
+        A: Into<Option<Address>>,
+        B: Into<Option<BlockId>>,
+        P: Tokenize,
+    {
+        options
+            .call_options
+            .get_or_insert_with(Default::default)
+            .set_replication(Replication::NonReplicated);
+        self.query_with(func, params, from, options, block, false)
+    }
+
+    fn query_with<R, A, B, P>(
+        &self,
+        func: &str,
+        params: P,
+        from: A,
+        mut options: Options,
+        block: B,
+        replicated_only: bool,
+    ) -> impl Future<Output = Result<R>> + '_
+    where
+        R: Detokenize,
+        A: Into<Option<Address>>,
+        B: Into<Option<BlockId>>,
+        P: Tokenize,
+    {
+        let refusal = format!("query of {} requires a replicated outcall", func);
+        let non_replicated = move || Error::Api(crate::Error::NonReplicated(refusal.clone()));
+        let call_options = options.call_options.get_or_insert_with(Default::default);
+        let provenance = replicated_only.then(Provenance::new);
+        if let Some(provenance) = &provenance {
+            call_options.track(provenance.clone());
+        }
+        let function = if replicated_only && call_options.replication() == Replication::NonReplicated {
+            Err(non_replicated())
+        } else {
+            self.abi.function(func).map_err(Error::from)
+        };
+        let result = function
             .and_then(|function| {
                 function
                     .encode_input(&params.into_tokens())
                     .map(|call| (call, function))
+                    .map_err(Error::from)
             })
             .map(|(call, function)| {
                 let call_future = self.eth.call(
@@ -306,9 +363,12 @@ impl<T: Transport> Contract<T> {
             });
         // NOTE for the batch transport to work correctly, we must call `transport.execute` without ever polling the future,
         // hence it cannot be a fully `async` function.
-        async {
+        async move {
             let (call_future, function) = result?;
             let bytes = call_future.await?;
+            if provenance.is_some_and(|provenance| provenance.replication() == Replication::NonReplicated) {
+                return Err(non_replicated());
+            }
             let output = function.decode_output(&bytes.0)?;
             R::from_tokens(output)
         }
@@ -370,22 +430,23 @@ impl<T: Transport> Contract<T> {
 
 // #[cfg(feature = "signing")]
 mod contract_signing {
-    use std::str::FromStr;
-
     use super::*;
     use crate::{
         api::Accounts,
+        signing::Signer,
         types::{SignedTransaction, TransactionParameters},
     };
 
     impl<T: Transport> Contract<T> {
-        pub async fn sign(
+        /// Sign a contract call transaction with `signer`.
+        ///
+        /// The gas limit is estimated from the signer's address unless given in `options`.
+        pub async fn sign<S: Signer + ?Sized>(
             &self,
             func: &str,
             params: impl Tokenize,
             options: Options,
-            from: String,
-            key_info: KeyInfo,
+            signer: &S,
             chain_id: u64,
         ) -> crate::Result<SignedTransaction> {
             let fn_data = self
@@ -410,37 +471,25 @@ mod contract_signing {
             tx.gas = if let Some(gas) = options.gas {
                 gas
             } else {
-                match self
-                    ._estimate_gas(
-                        Address::from_str(&from.to_string().as_str()).unwrap(),
-                        &tx,
-                        options.call_options.unwrap_or_default(),
-                    )
-                    .await
-                {
-                    Ok(gas) => gas,
-                    Err(e) => {
-                        return Err(e.into());
-                    }
-                }
+                self._estimate_gas(signer.address().await?, &tx, options.call_options.unwrap_or_default())
+                    .await?
             };
             if let Some(value) = options.value {
                 tx.value = value;
             }
-            accounts.sign_transaction(tx, from, key_info, chain_id).await
+            accounts.sign_transaction(tx, signer, chain_id).await
         }
 
         /// Submit contract call transaction to the transaction pool.
         ///
         /// Note this function DOES NOT wait for any confirmations, so there is no guarantees that the call is actually executed.
         /// If you'd rather wait for block inclusion, please use [`signed_call_with_confirmations`] instead.
-        pub async fn signed_call(
+        pub async fn signed_call<S: Signer + ?Sized>(
             &self,
             func: &str,
             params: impl Tokenize,
             options: Options,
-            from: String,
-            key_info: KeyInfo,
+            signer: &S,
             chain_id: u64,
         ) -> crate::Result<H256> {
             let signed = self
@@ -451,8 +500,7 @@ mod contract_signing {
                         call_options: None,
                         ..options.clone()
                     },
-                    from,
-                    key_info,
+                    signer,
                     chain_id,
                 )
                 .await?;
@@ -466,14 +514,13 @@ mod contract_signing {
         //
         // This function will wait for block inclusion of the transaction before returning.
         // If you'd rather just submit transaction and receive it's hash, please use [`signed_call`] instead.
-        pub async fn signed_call_with_confirmations(
+        pub async fn signed_call_with_confirmations<S: Signer + ?Sized>(
             &self,
             func: &str,
             params: impl Tokenize,
             options: Options,
-            from: String,
             confirmations: usize,
-            key_info: KeyInfo,
+            signer: &S,
             chain_id: u64,
         ) -> crate::Result<TransactionReceipt> {
             let poll_interval = time::Duration::from_secs(1);
@@ -485,8 +532,7 @@ mod contract_signing {
                         call_options: None,
                         ..options.clone()
                     },
-                    from,
-                    key_info,
+                    signer,
                     chain_id,
                 )
                 .await?;
@@ -505,14 +551,20 @@ mod contract_signing {
 
 #[cfg(test)]
 mod tests {
-    use super::{Contract, Options};
+    use super::{Contract, Error, Options};
     use crate::{
         api::{self, Namespace},
         rpc,
-        transports::test::TestTransport,
+        signing::LocalSigner,
+        transports::{
+            ic_http_client::{CallOptions, Replication},
+            test::TestTransport,
+            ICHttp, NativeOutcallBackend,
+        },
         types::{Address, BlockId, BlockNumber, H256, U256},
         Transport,
     };
+    use ic_cdk::api::management_canister::http_request::{CanisterHttpRequestArgument, HttpResponse, TransformArgs};
 
     fn contract<T: Transport>(transport: &T) -> Contract<&T> {
         let eth = api::Eth::new(transport);
@@ -583,6 +635,92 @@ mod tests {
         assert_eq!(result, "Hello World!".to_owned());
     }
 
+    #[test]
+    fn should_refuse_non_replicated_queries() {
+        let transport = TestTransport::default();
+        let mut call_options = CallOptions::default();
+        call_options.set_replication(Replication::NonReplicated);
+
+        let result: Result<String, _> = {
+            let token = contract(&transport);
+            futures::executor::block_on(token.query(
+                "name",
+                (),
+                None,
+                Options::with(|options| options.call_options = Some(call_options)),
+                None,
+            ))
+        };
+
+        assert!(matches!(result, Err(Error::Api(crate::Error::NonReplicated(_)))));
+        transport.assert_no_more_requests();
+    }
+
+    /// Transport making every outcall non-replicated, whatever the call options ask for.
+    #[derive(Debug, Clone)]
+    struct ForceNonReplicated<T>(T);
+
+    impl<T: Transport> Transport for ForceNonReplicated<T> {
+        type Out = T::Out;
+
+        fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (crate::RequestId, rpc::Call) {
+            self.0.prepare(method, params)
+        }
+
+        fn send(&self, id: crate::RequestId, request: rpc::Call, mut options: CallOptions) -> Self::Out {
+            options.set_replication(Replication::NonReplicated);
+            self.0.send(id, request, options)
+        }
+    }
+
+    fn name_transport() -> ICHttp {
+        let backend = NativeOutcallBackend::new(|_: &CanisterHttpRequestArgument| HttpResponse {
+            status: 200u8.into(),
+            headers: vec![],
+            body: br#"{"jsonrpc":"2.0","id":0,"result":"0x0000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000000c48656c6c6f20576f726c64210000000000000000000000000000000000000000"}"#.to_vec(),
+        })
+        .with_transform("transform", |args: TransformArgs| args.response);
+        ICHttp::new("https://rpc.example.com", None)
+            .unwrap()
+            .with_backend(backend)
+    }
+
+    #[test]
+    fn should_refuse_results_of_outcalls_a_transport_made_non_replicated() {
+        let replicated = name_transport();
+        let non_replicated = ForceNonReplicated(name_transport());
+
+        let result: Result<String, _> =
+            futures::executor::block_on(contract(&replicated).query("name", (), None, Options::default(), None));
+        assert_eq!(result.unwrap(), "Hello World!");
+
+        let result: Result<String, _> =
+            futures::executor::block_on(contract(&non_replicated).query("name", (), None, Options::default(), None));
+        assert!(matches!(result, Err(Error::Api(crate::Error::NonReplicated(_)))));
+
+        let result: Result<String, _> = futures::executor::block_on(contract(&non_replicated).query_non_replicated(
+            "name",
+            (),
+            None,
+            Options::default(),
+            None,
+        ));
+        assert_eq!(result.unwrap(), "Hello World!");
+    }
+
+    #[test]
+    fn should_query_non_replicated_on_request() {
+        let mut transport = TestTransport::default();
+        transport.set_response(rpc::Value::String("0x0000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000000c48656c6c6f20576f726c64210000000000000000000000000000000000000000".into()));
+
+        let result: String = {
+            let token = contract(&transport);
+            futures::executor::block_on(token.query_non_replicated("name", (), None, Options::default(), None)).unwrap()
+        };
+
+        assert_eq!(result, "Hello World!".to_owned());
+    }
+
     #[test]
     fn should_query_with_params() {
         // given
@@ -651,6 +789,32 @@ mod tests {
         assert_eq!(result, 5.into());
     }
 
+    #[test]
+    fn should_sign_a_contract_call_with_a_signer() {
+        // given
+        let mut transport = TestTransport::default();
+        transport.add_response(rpc::Value::String("0x5208".into()));
+        transport.add_response(rpc::Value::String(format!("{:?}", H256::from_low_u64_be(5))));
+        let signer = LocalSigner::from_slice(&[1; 32]).unwrap();
+
+        let result = {
+            let token = contract(&transport);
+            let options = Options::with(|options| {
+                options.nonce = Some(0.into());
+                options.gas_price = Some(1.into());
+            });
+
+            // when
+            futures::executor::block_on(token.signed_call("name", (), options, &signer, 1))
+        };
+
+        // then
+        transport.assert_request("eth_estimateGas", &["{\"data\":\"0x06fdde03\",\"from\":\"0x1a642f0e3c3af545e7acbd38b07251b3990914f1\",\"gasPrice\":\"0x1\",\"to\":\"0x0000000000000000000000000000000000000001\",\"value\":\"0x0\"}".into()]);
+        transport.assert_request("eth_sendRawTransaction", &["\"0xf8638001825208940000000000000000000000000000000000000001808406fdde0325a084a61a94ac1dad13e67d8beeb87aa880f52b088b2a1669fa35cc4bc82ef435b5a030d98f440573c8594e17e1081ba3c69cdf4f5e70cb66d8217099372f0f48fd90\"".into()]);
+        transport.assert_no_more_requests();
+        assert!(result.is_ok());
+    }
+
     #[test]
     fn should_query_single_parameter_function() {
         // given
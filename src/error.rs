@@ -1,6 +1,7 @@
 //! Web3 Error
-use crate::rpc::error::Error as RPCError;
+use crate::rpc::{self, error::Error as RPCError};
 use derive_more::{Display, From};
+use ic_cdk::api::call::RejectionCode;
 use serde_json::Error as SerdeError;
 use std::io::Error as IoError;
 
@@ -16,6 +17,18 @@ pub enum TransportError {
     /// Arbitrary, developer-readable description of the occurred error.
     #[display(fmt = "{}", _0)]
     Message(String),
+    /// The IC rejected the outcall or inter-canister call.
+    #[display(fmt = "rejected with {:?}: {}", _0, _1)]
+    Rejected(RejectionCode, String),
+}
+
+/// Answer a single endpoint gave to a call dispatched to several providers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderAnswer {
+    /// Label of the endpoint, usually its URL.
+    pub endpoint: String,
+    /// The decoded value or a description of the failure.
+    pub answer: std::result::Result<rpc::Value, String>,
 }
 
 /// Errors which can occur when attempting to generate resource uri.
@@ -35,6 +48,18 @@ pub enum Error {
     #[display(fmt = "Transport error: {}" _0)]
     #[from(ignore)]
     Transport(TransportError),
+    /// providers did not agree on the result
+    #[display(fmt = "Inconsistent providers: {:?}", _0)]
+    #[from(ignore)]
+    InconsistentProviders(Vec<ProviderAnswer>),
+    /// caller exceeded its cycles budget or rate limit
+    #[display(fmt = "Limit exceeded: {}", _0)]
+    #[from(ignore)]
+    LimitExceeded(String),
+    /// result of a non-replicated outcall refused on a path requiring consensus
+    #[display(fmt = "Non-replicated result refused: {}", _0)]
+    #[from(ignore)]
+    NonReplicated(String),
     /// rpc error
     #[display(fmt = "RPC error: {:?}", _0)]
     Rpc(RPCError),
@@ -44,6 +69,9 @@ pub enum Error {
     /// recovery error
     #[display(fmt = "Recovery error: {}", _0)]
     Recovery(crate::signing::RecoveryError),
+    /// signing error
+    #[display(fmt = "Signing error: {}", _0)]
+    Signing(crate::signing::SigningError),
     /// web3 internal error
     #[display(fmt = "Internal Web3 error")]
     Internal,
@@ -53,10 +81,18 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         use self::Error::*;
         match *self {
-            Unreachable | Decoder(_) | InvalidResponse(_) | Transport { .. } | Internal => None,
+            Unreachable
+            | Decoder(_)
+            | InvalidResponse(_)
+            | Transport { .. }
+            | InconsistentProviders(_)
+            | LimitExceeded(_)
+            | NonReplicated(_)
+            | Internal => None,
             Rpc(ref e) => Some(e),
             Io(ref e) => Some(e),
             Recovery(ref e) => Some(e),
+            Signing(ref e) => Some(e),
         }
     }
 }
@@ -75,9 +111,13 @@ impl Clone for Error {
             Decoder(s) => Decoder(s.clone()),
             InvalidResponse(s) => InvalidResponse(s.clone()),
             Transport(s) => Transport(s.clone()),
+            InconsistentProviders(a) => InconsistentProviders(a.clone()),
+            LimitExceeded(s) => LimitExceeded(s.clone()),
+            NonReplicated(s) => NonReplicated(s.clone()),
             Rpc(e) => Rpc(e.clone()),
             Io(e) => Io(IoError::from(e.kind())),
             Recovery(e) => Recovery(e.clone()),
+            Signing(e) => Signing(e.clone()),
             Internal => Internal,
         }
     }
@@ -89,11 +129,16 @@ impl PartialEq for Error {
         use self::Error::*;
         match (self, other) {
             (Unreachable, Unreachable) | (Internal, Internal) => true,
-            (Decoder(a), Decoder(b)) | (InvalidResponse(a), InvalidResponse(b)) => a == b,
+            (Decoder(a), Decoder(b))
+            | (InvalidResponse(a), InvalidResponse(b))
+            | (LimitExceeded(a), LimitExceeded(b))
+            | (NonReplicated(a), NonReplicated(b)) => a == b,
             (Transport(a), Transport(b)) => a == b,
+            (InconsistentProviders(a), InconsistentProviders(b)) => a == b,
             (Rpc(a), Rpc(b)) => a == b,
             (Io(a), Io(b)) => a.kind() == b.kind(),
             (Recovery(a), Recovery(b)) => a == b,
+            (Signing(a), Signing(b)) => a == b,
             _ => false,
         }
     }
@@ -1,13 +1,13 @@
 //! Web3 helpers.
 
-use crate::{error, rpc, Error};
+use crate::{error, rpc, Error, RequestId};
 use futures::{
     task::{Context, Poll},
     Future,
 };
 use pin_project::pin_project;
 use serde::de::DeserializeOwned;
-use std::{marker::PhantomData, pin::Pin};
+use std::{collections::HashMap, marker::PhantomData, pin::Pin};
 
 /// Takes any type which is deserializable from rpc::Value and such a value and
 /// yields the deserialized value
@@ -68,6 +68,21 @@ pub fn build_request(id: usize, method: &str, params: Vec<rpc::Value>) -> rpc::C
     })
 }
 
+/// Method and params of a JSON-RPC call, params as a JSON array or object.
+pub fn method_and_params(call: &rpc::Call) -> (String, rpc::Value) {
+    let (method, params) = match call {
+        rpc::Call::MethodCall(call) => (&call.method, &call.params),
+        rpc::Call::Notification(call) => (&call.method, &call.params),
+        rpc::Call::Invalid { .. } => return (String::new(), rpc::Value::Null),
+    };
+    let params = match params {
+        rpc::Params::None => rpc::Value::Array(vec![]),
+        rpc::Params::Array(params) => rpc::Value::Array(params.clone()),
+        rpc::Params::Map(params) => rpc::Value::Object(params.clone()),
+    };
+    (method.clone(), params)
+}
+
 /// Parse bytes slice into JSON-RPC response.
 /// It looks for arbitrary_precision feature as a temporary workaround for https://github.com/tomusdrw/rust-web3/issues/460.
 pub fn to_response_from_slice(response: &[u8]) -> error::Result<rpc::Response> {
@@ -105,6 +120,96 @@ pub fn to_result_from_output(output: rpc::Output) -> error::Result<rpc::Value> {
     }
 }
 
+/// Parse the body of an HTTP response into a JSON-RPC response.
+pub fn to_response_from_body<T: DeserializeOwned>(body: &[u8]) -> error::Result<T> {
+    arbitrary_precision_deserialize_workaround(body).map_err(|err| {
+        Error::Transport(error::TransportError::Message(format!(
+            "failed to deserialize response: {}: {}",
+            err,
+            String::from_utf8_lossy(body)
+        )))
+    })
+}
+
+/// Take the outputs of a batch response.
+///
+/// Nodes refusing a batch, and transforms turning a failed outcall into an error envelope, answer
+/// with a single error instead.
+pub fn to_outputs_from_batch_response(response: rpc::Response) -> error::Result<Vec<rpc::Output>> {
+    match response {
+        rpc::Response::Batch(outputs) => Ok(outputs),
+        rpc::Response::Single(output) => to_result_from_output(output)
+            .and_then(|_| Err(Error::InvalidResponse("expected a batch response".to_string()))),
+    }
+}
+
+/// Parse the outputs of a batch into `Result`s in the order of the request `ids`.
+///
+/// According to the jsonrpc specification batch responses can be returned in any order so we need to
+/// restore the intended order.
+pub fn to_results_from_batch(
+    ids: &[RequestId],
+    outputs: Vec<rpc::Output>,
+) -> error::Result<Vec<error::Result<rpc::Value>>> {
+    if ids.len() != outputs.len() {
+        return Err(Error::InvalidResponse("unexpected number of responses".to_string()));
+    }
+    let mut outputs = outputs
+        .into_iter()
+        .map(|output| Ok((id_of_output(&output)?, to_result_from_output(output))))
+        .collect::<error::Result<HashMap<_, _>>>()?;
+    ids.iter()
+        .map(|id| {
+            outputs
+                .remove(id)
+                .ok_or_else(|| Error::InvalidResponse(format!("batch response is missing id {}", id)))
+        })
+        .collect()
+}
+
+fn id_of_output(output: &rpc::Output) -> error::Result<RequestId> {
+    let id = match output {
+        rpc::Output::Success(success) => &success.id,
+        rpc::Output::Failure(failure) => &failure.id,
+    };
+    match id {
+        rpc::Id::Num(num) => Ok(*num as RequestId),
+        _ => Err(Error::InvalidResponse("response id is not u64".to_string())),
+    }
+}
+
+/// Current time in nanoseconds since the UNIX epoch.
+///
+/// Uses the IC system time inside a canister and the host clock elsewhere, so that time based
+/// bookkeeping can be exercised by native tests.
+pub fn now_nanos() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::api::time()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+    }
+}
+
+/// Instructions executed so far in the current call context, across awaits.
+///
+/// Always zero outside of a canister.
+pub fn call_context_instructions() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        ic_cdk::api::call_context_instruction_counter()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        0
+    }
+}
+
 #[macro_use]
 #[cfg(test)]
 pub mod tests {
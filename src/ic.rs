@@ -1,13 +1,16 @@
 //! IC's threshold ECDSA related functions
 
-use crate::signing;
-use crate::types::{Address, Recovery};
+use crate::error::{Error, TransportError};
+use crate::keys::{DerivationPath, DerivationPathError, KeyManager};
+use crate::signing::{self, RecoveryError, Signer, SigningError};
+use crate::types::{Address, Recovery, SignedData, H256};
 use candid::{CandidType, Principal};
-use libsecp256k1::{recover, Message, PublicKey, PublicKeyFormat, RecoveryId, Signature};
+use futures::future::{BoxFuture, FutureExt};
+use libsecp256k1::{PublicKey, PublicKeyFormat};
 use serde::Serialize;
-use std::str::FromStr;
 
-const ECDSA_SIGN_CYCLES: u64 = 3 * 10_000_000_000;
+/// Cycles attached to `sign_with_ecdsa` unless `KeyInfo` says otherwise.
+pub const ECDSA_SIGN_CYCLES: u64 = 3 * 10_000_000_000;
 // pub type Address = [u8; 20];
 
 // #[derive(CandidType, Serialize, Debug, Clone)]
@@ -27,6 +30,8 @@ pub struct KeyInfo {
 
 /// get public key from ic,
 /// derivation_path: 4-byte big-endian encoding of an unsigned integer less than 2^31
+///
+/// Every call reaches the management canister, [`KeyManager`] caches keys instead.
 pub async fn get_public_key(
     canister_id: Option<Principal>,
     derivation_path: Vec<Vec<u8>>,
@@ -36,8 +41,7 @@ pub async fn get_public_key(
         curve: EcdsaCurve::Secp256k1,
         name: key_name,
     };
-    let ic_canister_id = "aaaaa-aa";
-    let ic = Principal::from_str(&ic_canister_id).unwrap();
+    let ic = Principal::management_canister();
 
     let request = EcdsaPublicKeyArgument {
         canister_id: canister_id,
@@ -66,6 +70,11 @@ pub fn pubkey_to_address(pubkey: &[u8]) -> Result<Address, String> {
 }
 
 /// get canister's eth address
+///
+/// Without a derivation path, the canister id is used, so every user of the canister shares the address;
+/// [`DerivationPath::for_principal`] gives each user an address of their own.
+///
+/// Every call reaches the management canister, [`KeyManager`] caches addresses instead.
 pub async fn get_eth_addr(
     canister_id: Option<Principal>,
     derivation_path: Option<Vec<Vec<u8>>>,
@@ -88,7 +97,9 @@ pub async fn get_eth_addr(
 
 /// use ic's threshold ecdsa to sign a message
 pub async fn ic_raw_sign(message: Vec<u8>, key_info: KeyInfo) -> Result<Vec<u8>, String> {
-    assert!(message.len() == 32);
+    if message.len() != 32 {
+        return Err(SigningError::InvalidMessage.to_string());
+    }
 
     let key_id = EcdsaKeyId {
         curve: EcdsaCurve::Secp256k1,
@@ -112,31 +123,236 @@ pub async fn ic_raw_sign(message: Vec<u8>, key_info: KeyInfo) -> Result<Vec<u8>,
     Ok(res.signature)
 }
 
-// recover address from signature
-// rec_id < 4
-pub fn recover_address(msg: Vec<u8>, sig: Vec<u8>, rec_id: u8) -> String {
-    let message = Message::parse_slice(&msg).unwrap();
-    let signature = Signature::parse_overflowing_slice(&sig).unwrap();
-    let recovery_id = RecoveryId::parse(rec_id).unwrap();
+/// Signs with a threshold ECDSA key of the IC.
+#[derive(Debug, Clone)]
+pub struct IcSigner {
+    key_info: KeyInfo,
+    keys: Option<KeyManager>,
+}
 
-    match recover(&message, &signature, &recovery_id) {
-        Ok(pubkey) => {
-            let uncompressed_pubkey = pubkey.serialize();
-            // let hash = keccak256_hash(&uncompressed_pubkey[1..65]);
-            let hash = signing::keccak256(&uncompressed_pubkey[1..65]);
-            let mut result = [0u8; 20];
-            result.copy_from_slice(&hash[12..]);
-            hex::encode(result)
+impl IcSigner {
+    /// Signs with the key and derivation path of `key_info`.
+    pub fn new(key_info: KeyInfo) -> Self {
+        IcSigner { key_info, keys: None }
+    }
+
+    /// Signs on behalf of `caller` with `path` of the master key `key_name`, failing unless the
+    /// path belongs to the caller, so that callers can't sign with each other's keys.
+    pub fn for_owner(
+        caller: &Principal,
+        path: &DerivationPath,
+        key_name: impl Into<String>,
+    ) -> Result<Self, DerivationPathError> {
+        path.ensure_owned_by(caller)?;
+        Ok(Self::new(path.key_info(key_name)))
+    }
+
+    /// Look public keys up in `keys` instead of fetching them for every signature. Keys are
+    /// fetched as before while `keys` manage another master key than `key_info`.
+    pub fn with_key_manager(mut self, keys: KeyManager) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    /// The key and derivation path signatures are made with.
+    pub fn key_info(&self) -> &KeyInfo {
+        &self.key_info
+    }
+}
+
+async fn ic_public_key(key_info: &KeyInfo, keys: Option<&KeyManager>) -> crate::Result<PublicKey> {
+    let public_key = match keys {
+        Some(keys) if keys.key_name() == key_info.key_name => keys.public_key(&key_info.derivation_path).await?,
+        _ => get_public_key(None, key_info.derivation_path.clone(), key_info.key_name.clone())
+            .await
+            .map_err(|err| Error::Signing(SigningError::Failed(err)))?,
+    };
+    PublicKey::parse_slice(&public_key, Some(PublicKeyFormat::Compressed))
+        .map_err(|_| Error::Signing(SigningError::InvalidKey))
+}
+
+impl Signer for IcSigner {
+    fn address(&self) -> BoxFuture<'static, crate::Result<Address>> {
+        let (key_info, keys) = (self.key_info.clone(), self.keys.clone());
+        async move {
+            Ok(signing::public_key_address(
+                &ic_public_key(&key_info, keys.as_ref()).await?,
+            ))
         }
-        Err(_) => "".into(),
+        .boxed()
+    }
+
+    fn sign_hash(&self, hash: H256) -> BoxFuture<'static, crate::Result<signing::Signature>> {
+        let (key_info, keys) = (self.key_info.clone(), self.keys.clone());
+        async move {
+            let public_key = ic_public_key(&key_info, keys.as_ref()).await?;
+            let signature = ic_raw_sign(hash.as_bytes().to_vec(), key_info)
+                .await
+                .map_err(|err| Error::Signing(SigningError::Failed(err)))?;
+            let recovery_id = signing::recovery_id(hash, &signature, &public_key)?;
+            Ok(signing::Signature {
+                v: recovery_id as u64,
+                r: H256::from_slice(&signature[..32]),
+                s: H256::from_slice(&signature[32..]),
+            })
+        }
+        .boxed()
+    }
+
+    fn sign_cycles(&self) -> u64 {
+        self.key_info.ecdsa_sign_cycles.unwrap_or(ECDSA_SIGN_CYCLES)
+    }
+
+    fn verifies_recovery_id(&self) -> bool {
+        true
+    }
+}
+
+/// Method of a signer canister returning its address, `() -> (variant { Ok : blob; Err : text })`.
+pub const REMOTE_ADDRESS_METHOD: &str = "eth_address";
+/// Method of a signer canister signing a 32-byte hash, `(blob) -> (variant { Ok : blob; Err : text })`.
+///
+/// Signatures are 65 bytes, `r`, `s` and the recovery id.
+pub const REMOTE_SIGN_METHOD: &str = "sign_hash";
+
+/// Signs through another canister holding the key, e.g. one shared by several canisters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteSigner {
+    canister_id: Principal,
+}
+
+impl RemoteSigner {
+    /// Signs through the signer canister `canister_id`.
+    pub fn new(canister_id: Principal) -> Self {
+        RemoteSigner { canister_id }
+    }
+
+    /// The signer canister.
+    pub fn canister_id(&self) -> Principal {
+        self.canister_id
+    }
+}
+
+fn remote_result<T>(result: ic_cdk::api::call::CallResult<(Result<T, String>,)>) -> crate::Result<T> {
+    match result {
+        Ok((Ok(value),)) => Ok(value),
+        Ok((Err(err),)) => Err(Error::Signing(SigningError::Failed(err))),
+        Err((code, message)) => Err(Error::Transport(TransportError::Rejected(code, message))),
     }
 }
 
-pub fn verify(addr: String, message: Vec<u8>, signature: Vec<u8>) -> bool {
-    let (sig, rec_id) = Recovery::from_raw_signature(message.clone(), signature)
-        .unwrap()
-        .as_signature()
+impl Signer for RemoteSigner {
+    fn address(&self) -> BoxFuture<'static, crate::Result<Address>> {
+        ic_cdk::call(self.canister_id, REMOTE_ADDRESS_METHOD, ())
+            .map(|result| match remote_result::<Vec<u8>>(result)? {
+                address if address.len() == 20 => Ok(Address::from_slice(&address)),
+                _ => Err(Error::Signing(SigningError::InvalidKey)),
+            })
+            .boxed()
+    }
+
+    fn sign_hash(&self, hash: H256) -> BoxFuture<'static, crate::Result<signing::Signature>> {
+        ic_cdk::call(self.canister_id, REMOTE_SIGN_METHOD, (hash.as_bytes().to_vec(),))
+            .map(|result| match remote_result::<Vec<u8>>(result)? {
+                signature if signature.len() == 65 && signature[64] < 2 => Ok(signing::Signature {
+                    v: signature[64] as u64,
+                    r: H256::from_slice(&signature[..32]),
+                    s: H256::from_slice(&signature[32..64]),
+                }),
+                _ => Err(Error::Signing(SigningError::InvalidSignature)),
+            })
+            .boxed()
+    }
+}
+
+/// Sign `message` like `personal_sign` with the threshold ECDSA key of `key_info`.
+///
+/// The message is enveloped according to EIP-191 and the signature has `v` of 27 or 28, so that
+/// off-chain verifiers and Solidity's `ecrecover` accept it. Use `Accounts::sign_message` with an
+/// [`IcSigner`] to look the public key up in a [`KeyManager`] instead of fetching it.
+pub async fn sign_personal_message(message: Vec<u8>, key_info: KeyInfo) -> crate::Result<SignedData> {
+    let signer = IcSigner::new(key_info);
+    let signature = signer.sign_message(&message).await?;
+    signing::check_signature(&signer, signing::hash_message(&message), &signature).await?;
+    signing::signed_message(&message, signature).map_err(Error::Signing)
+}
+
+/// Recovers the address that made the 64-byte `signature` of the 32-byte `msg` with recovery id `rec_id`.
+///
+/// Same as [`signing::recover`], which refuses signatures whose `r` or `s` overflow the curve order.
+pub fn recover_address(msg: Vec<u8>, sig: Vec<u8>, rec_id: u8) -> Result<Address, RecoveryError> {
+    signing::recover(&msg, &sig, rec_id.into())
+}
+
+/// Whether the 65-byte `signature` (with `v` of 27/28 or EIP-155) of the 32-byte `message` was made by `addr`.
+///
+/// Malformed messages and signatures are never valid.
+pub fn verify(addr: Address, message: Vec<u8>, signature: Vec<u8>) -> bool {
+    Recovery::from_raw_signature(message.clone(), signature)
+        .ok()
+        .and_then(|recovery| recovery.as_signature())
+        .and_then(|(sig, rec_id)| recover_address(message, sig.to_vec(), rec_id as u8).ok())
+        .is_some_and(|recovered| recovered == addr)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::signing::LocalSigner;
+    use hex_literal::hex;
+
+    fn signed_hash() -> (LocalSigner, Vec<u8>, Vec<u8>) {
+        let signer = LocalSigner::from_slice(&hex!(
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+        ))
         .unwrap();
-    let rec_addr = recover_address(message, sig.to_vec(), rec_id as u8);
-    return rec_addr == addr;
+        let hash = signing::keccak256(b"Some data");
+        let signature = futures::executor::block_on(signer.sign_hash(hash.into())).unwrap();
+        let raw = [
+            signature.r.as_bytes(),
+            signature.s.as_bytes(),
+            &[signature.v as u8 + 27],
+        ]
+        .concat();
+        (signer, hash.to_vec(), raw)
+    }
+
+    #[test]
+    fn should_recover_and_verify_the_signing_address() {
+        let (signer, hash, raw) = signed_hash();
+        let address = signing::public_key_address(&signer.public_key());
+
+        assert_eq!(
+            recover_address(hash.clone(), raw[..64].to_vec(), raw[64] - 27),
+            Ok(address)
+        );
+        assert!(verify(address, hash.clone(), raw.clone()));
+        assert!(!verify(Address::zero(), hash, raw));
+    }
+
+    #[test]
+    fn should_reject_malformed_input_without_panicking() {
+        let (signer, hash, raw) = signed_hash();
+        let address = signing::public_key_address(&signer.public_key());
+
+        assert_eq!(
+            recover_address(hash[..31].to_vec(), raw[..64].to_vec(), raw[64]),
+            Err(RecoveryError::InvalidMessage)
+        );
+        assert_eq!(
+            recover_address(hash.clone(), raw[..63].to_vec(), raw[64]),
+            Err(RecoveryError::InvalidSignature)
+        );
+        assert_eq!(
+            recover_address(hash.clone(), raw[..64].to_vec(), 4),
+            Err(RecoveryError::InvalidSignature)
+        );
+        assert_eq!(
+            recover_address(hash.clone(), [&[0xff; 32][..], &raw[32..64]].concat(), raw[64] - 27),
+            Err(RecoveryError::InvalidSignature)
+        );
+        assert!(!verify(address, hash[..31].to_vec(), raw.clone()));
+        assert!(!verify(address, hash.clone(), raw[..64].to_vec()));
+        assert!(!verify(address, hash, [&raw[..64], &[9]].concat()));
+    }
 }
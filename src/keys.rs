@@ -0,0 +1,545 @@
+//! Derivation paths and cached public keys of threshold ECDSA keys.
+
+use crate::{
+    error::{Error, Result, TransportError},
+    ic::KeyInfo,
+    signing::{self, SigningError},
+    types::Address,
+};
+use candid::{CandidType, Principal};
+use hmac::{Hmac, Mac, NewMac};
+use ic_cdk::api::management_canister::ecdsa::{ecdsa_public_key, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument};
+use ic_stable_structures::{storable::Bound, Memory, StableBTreeMap, Storable};
+use libsecp256k1::{PublicKey, PublicKeyFormat, SecretKey};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::Sha512;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    sync::Arc,
+};
+
+/// Most components the management canister accepts in a derivation path.
+pub const MAX_DERIVATION_PATH_LEN: usize = 255;
+
+/// Error building or using a derivation path.
+#[derive(Debug, derive_more::Display, PartialEq, Eq, Clone)]
+pub enum DerivationPathError {
+    /// The path has more than [`MAX_DERIVATION_PATH_LEN`] components.
+    #[display(
+        fmt = "Derivation path has {} components, at most {} are allowed.",
+        _0,
+        MAX_DERIVATION_PATH_LEN
+    )]
+    TooLong(usize),
+    /// The path doesn't belong to the principal using it.
+    #[display(fmt = "Derivation path is not owned by {}.", _0)]
+    NotOwned(Principal),
+}
+impl std::error::Error for DerivationPathError {}
+
+/// Derivation path of a threshold ECDSA key, within the management canister's limits.
+///
+/// Paths of [`DerivationPath::for_principal`] and [`DerivationPath::for_account`] start with the
+/// principal they belong to, so that every user of a canister gets addresses of their own and
+/// [`DerivationPath::ensure_owned_by`] can tell who may sign with them.
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(try_from = "Vec<Vec<u8>>")]
+pub struct DerivationPath(Vec<Vec<u8>>);
+
+impl TryFrom<Vec<Vec<u8>>> for DerivationPath {
+    type Error = DerivationPathError;
+
+    fn try_from(components: Vec<Vec<u8>>) -> std::result::Result<Self, Self::Error> {
+        DerivationPath::new(components)
+    }
+}
+
+impl DerivationPath {
+    /// Starts building a path.
+    pub fn builder() -> DerivationPathBuilder {
+        DerivationPathBuilder::default()
+    }
+
+    /// Path made of `components`.
+    pub fn new(components: Vec<Vec<u8>>) -> std::result::Result<Self, DerivationPathError> {
+        if components.len() > MAX_DERIVATION_PATH_LEN {
+            return Err(DerivationPathError::TooLong(components.len()));
+        }
+        Ok(DerivationPath(components))
+    }
+
+    /// Path of the single key of `principal`.
+    pub fn for_principal(principal: &Principal) -> Self {
+        DerivationPath(vec![principal.as_slice().to_vec()])
+    }
+
+    /// Path of the account `index` of `principal`.
+    pub fn for_account(principal: &Principal, index: u32) -> Self {
+        DerivationPath(vec![principal.as_slice().to_vec(), index.to_be_bytes().to_vec()])
+    }
+
+    /// The components of the path.
+    pub fn components(&self) -> &[Vec<u8>] {
+        &self.0
+    }
+
+    /// The principal the path belongs to, its first component.
+    pub fn owner(&self) -> Option<Principal> {
+        Principal::try_from_slice(self.0.first()?).ok()
+    }
+
+    /// Fails unless the path belongs to `caller`, e.g. before signing on behalf of the caller of
+    /// an update call.
+    pub fn ensure_owned_by(&self, caller: &Principal) -> std::result::Result<(), DerivationPathError> {
+        match self.owner() {
+            Some(owner) if owner == *caller => Ok(()),
+            _ => Err(DerivationPathError::NotOwned(*caller)),
+        }
+    }
+
+    /// Key info signing with the path of the master key `key_name`.
+    pub fn key_info(&self, key_name: impl Into<String>) -> KeyInfo {
+        KeyInfo {
+            derivation_path: self.0.clone(),
+            key_name: key_name.into(),
+            ecdsa_sign_cycles: None,
+        }
+    }
+}
+
+impl AsRef<[Vec<u8>]> for DerivationPath {
+    fn as_ref(&self) -> &[Vec<u8>] {
+        &self.0
+    }
+}
+
+impl From<DerivationPath> for Vec<Vec<u8>> {
+    fn from(path: DerivationPath) -> Self {
+        path.0
+    }
+}
+
+/// Builds a [`DerivationPath`] component by component.
+#[derive(Debug, Clone, Default)]
+pub struct DerivationPathBuilder {
+    components: Vec<Vec<u8>>,
+}
+
+impl DerivationPathBuilder {
+    /// Appends raw bytes.
+    pub fn component(mut self, component: impl Into<Vec<u8>>) -> Self {
+        self.components.push(component.into());
+        self
+    }
+
+    /// Appends the bytes of `principal`.
+    pub fn principal(self, principal: &Principal) -> Self {
+        self.component(principal.as_slice())
+    }
+
+    /// Appends `index` as 4 big-endian bytes.
+    pub fn index(self, index: u32) -> Self {
+        self.component(index.to_be_bytes())
+    }
+
+    /// Validates the path.
+    pub fn build(self) -> std::result::Result<DerivationPath, DerivationPathError> {
+        DerivationPath::new(self.components)
+    }
+}
+
+/// Public key of a derivation path, with its chain code and Ethereum address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedKey {
+    pub key_name: String,
+    pub derivation_path: Vec<Vec<u8>>,
+    /// Compressed SEC1 public key.
+    pub public_key: Vec<u8>,
+    pub chain_code: Vec<u8>,
+    pub address: Address,
+}
+
+impl CachedKey {
+    fn new(key_name: &str, derivation_path: &[Vec<u8>], public_key: &PublicKey, chain_code: &[u8]) -> Self {
+        CachedKey {
+            key_name: key_name.to_string(),
+            derivation_path: derivation_path.to_vec(),
+            public_key: public_key.serialize_compressed().to_vec(),
+            chain_code: chain_code.to_vec(),
+            address: signing::public_key_address(public_key),
+        }
+    }
+
+    fn parsed(&self) -> Option<(PublicKey, [u8; 32])> {
+        let public_key = PublicKey::parse_slice(&self.public_key, Some(PublicKeyFormat::Compressed)).ok()?;
+        Some((public_key, self.chain_code.as_slice().try_into().ok()?))
+    }
+}
+
+impl Storable for CachedKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_json::to_vec(self).expect("keys are serializable; qed"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_json::from_slice(&bytes).expect("keys are written by to_bytes; qed")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Derives the public key and chain code of `index` below `public_key`.
+///
+/// This is how the IC derives threshold ECDSA keys: SLIP-10 public derivation with indices of
+/// arbitrary bytes, retrying with the next chain code when the offset is out of range.
+pub fn derive_child(public_key: &PublicKey, chain_code: &[u8; 32], index: &[u8]) -> (PublicKey, [u8; 32]) {
+    let mut input = public_key.serialize_compressed().to_vec();
+    loop {
+        let mut mac = Hmac::<Sha512>::new_varkey(chain_code).expect("HMAC accepts keys of any size; qed");
+        mac.update(&input);
+        mac.update(index);
+        let output = mac.finalize().into_bytes();
+        let (offset, next_chain_code) = output.split_at(32);
+        let next_chain_code: [u8; 32] = next_chain_code.try_into().expect("HMAC-SHA512 output is 64 bytes; qed");
+        let child = if offset.iter().all(|byte| *byte == 0) {
+            Some(*public_key)
+        } else {
+            SecretKey::parse_slice(offset).ok().and_then(|offset| {
+                let mut child = *public_key;
+                child.tweak_add_assign(&offset).ok().map(|_| child)
+            })
+        };
+        match child {
+            Some(child) => return (child, next_chain_code),
+            None => input = [&[1u8][..], &next_chain_code].concat(),
+        }
+    }
+}
+
+/// Derives the public key and chain code of `path` below `public_key`, see [`derive_child`].
+pub fn derive_path(public_key: &PublicKey, chain_code: &[u8; 32], path: &[Vec<u8>]) -> (PublicKey, [u8; 32]) {
+    path.iter()
+        .fold((*public_key, *chain_code), |(public_key, chain_code), index| {
+            derive_child(&public_key, &chain_code, index)
+        })
+}
+
+#[derive(Debug)]
+struct State {
+    key_name: String,
+    local_derivation: bool,
+    keys: HashMap<Vec<Vec<u8>>, CachedKey>,
+}
+
+impl State {
+    /// Derives `path` from its longest cached prefix.
+    fn derive(&self, path: &[Vec<u8>]) -> Option<CachedKey> {
+        (0..=path.len()).rev().find_map(|len| {
+            let (public_key, chain_code) = self.keys.get(&path[..len])?.parsed()?;
+            let (public_key, chain_code) = derive_path(&public_key, &chain_code, &path[len..]);
+            Some(CachedKey::new(&self.key_name, path, &public_key, &chain_code))
+        })
+    }
+}
+
+/// Public keys and addresses of the canister's threshold ECDSA key, cached per derivation path.
+///
+/// Keys are fetched with the management canister's `ecdsa_public_key` once. With local
+/// derivation, the default, the key of the empty path is fetched instead and every other path
+/// is derived from it with its chain code, so that a single call serves all paths. Changing the
+/// key name forgets every key.
+///
+/// Keys live on the heap, [`KeyManager::persist`] and [`KeyManager::restore`] carry them over
+/// upgrades in a `StableBTreeMap`.
+#[derive(Debug, Clone)]
+pub struct KeyManager {
+    state: Arc<Mutex<State>>,
+}
+
+impl KeyManager {
+    /// Manages keys derived from the master key `key_name`.
+    pub fn new(key_name: impl Into<String>) -> Self {
+        KeyManager {
+            state: Arc::new(Mutex::new(State {
+                key_name: key_name.into(),
+                local_derivation: true,
+                keys: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Fetch the key of every path from the management canister instead of deriving it locally.
+    pub fn without_local_derivation(self) -> Self {
+        self.state.lock().local_derivation = false;
+        self
+    }
+
+    /// Name of the master key.
+    pub fn key_name(&self) -> String {
+        self.state.lock().key_name.clone()
+    }
+
+    /// Switches to the master key `key_name`, forgetting every key if it changed.
+    pub fn set_key_name(&self, key_name: impl Into<String>) {
+        let key_name = key_name.into();
+        let mut state = self.state.lock();
+        if state.key_name != key_name {
+            state.key_name = key_name;
+            state.keys.clear();
+        }
+    }
+
+    /// Number of cached keys.
+    pub fn len(&self) -> usize {
+        self.state.lock().keys.len()
+    }
+
+    /// Whether no key is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets every key.
+    pub fn clear(&self) {
+        self.state.lock().keys.clear();
+    }
+
+    /// Caches the compressed `public_key` and `chain_code` of `derivation_path`, e.g. as returned
+    /// by `ecdsa_public_key`.
+    pub fn insert(&self, derivation_path: &[Vec<u8>], public_key: &[u8], chain_code: &[u8]) -> Result<CachedKey> {
+        let public_key = PublicKey::parse_slice(public_key, Some(PublicKeyFormat::Compressed))
+            .map_err(|_| Error::Signing(SigningError::InvalidKey))?;
+        let mut state = self.state.lock();
+        let key = CachedKey::new(&state.key_name, derivation_path, &public_key, chain_code);
+        state.keys.insert(derivation_path.to_vec(), key.clone());
+        Ok(key)
+    }
+
+    /// The key of `derivation_path` if it's cached or can be derived from a cached key.
+    pub fn cached(&self, derivation_path: &[Vec<u8>]) -> Option<CachedKey> {
+        let mut state = self.state.lock();
+        if let Some(key) = state.keys.get(derivation_path) {
+            return Some(key.clone());
+        }
+        if !state.local_derivation {
+            return None;
+        }
+        let key = state.derive(derivation_path)?;
+        state.keys.insert(derivation_path.to_vec(), key.clone());
+        Some(key)
+    }
+
+    /// The key of `derivation_path`, fetched from the management canister unless cached.
+    pub async fn key(&self, derivation_path: &[Vec<u8>]) -> Result<CachedKey> {
+        if let Some(key) = self.cached(derivation_path) {
+            return Ok(key);
+        }
+        let (key_name, local_derivation) = {
+            let state = self.state.lock();
+            (state.key_name.clone(), state.local_derivation)
+        };
+        let fetched = if local_derivation { &[][..] } else { derivation_path };
+        let argument = EcdsaPublicKeyArgument {
+            canister_id: None,
+            derivation_path: fetched.to_vec(),
+            key_id: EcdsaKeyId {
+                curve: EcdsaCurve::Secp256k1,
+                name: key_name.clone(),
+            },
+        };
+        let (response,) = ecdsa_public_key(argument)
+            .await
+            .map_err(|(code, message)| Error::Transport(TransportError::Rejected(code, message)))?;
+        // Don't cache keys of a master key switched from in the meantime.
+        if self.key_name() != key_name {
+            return Err(Error::Signing(SigningError::Failed(
+                "key name changed while fetching the public key".into(),
+            )));
+        }
+        self.insert(fetched, &response.public_key, &response.chain_code)?;
+        self.cached(derivation_path)
+            .ok_or(Error::Signing(SigningError::InvalidKey))
+    }
+
+    /// Compressed public key of `derivation_path`.
+    pub async fn public_key(&self, derivation_path: &[Vec<u8>]) -> Result<Vec<u8>> {
+        Ok(self.key(derivation_path).await?.public_key)
+    }
+
+    /// Ethereum address of `derivation_path`.
+    pub async fn address(&self, derivation_path: &[Vec<u8>]) -> Result<Address> {
+        Ok(self.key(derivation_path).await?.address)
+    }
+
+    /// Writes the cached keys to `map`, replacing its content. Call it in `pre_upgrade`.
+    pub fn persist<M: Memory>(&self, map: &mut StableBTreeMap<String, CachedKey, M>) {
+        map.clear_new();
+        for key in self.state.lock().keys.values() {
+            map.insert(storage_key(&key.key_name, &key.derivation_path), key.clone());
+        }
+    }
+
+    /// Loads keys written by [`KeyManager::persist`], skipping keys of another master key. Call
+    /// it in `post_upgrade`.
+    pub fn restore<M: Memory>(&self, map: &StableBTreeMap<String, CachedKey, M>) {
+        let mut state = self.state.lock();
+        for (_, key) in map.iter() {
+            if key.key_name == state.key_name {
+                state.keys.insert(key.derivation_path.clone(), key);
+            }
+        }
+    }
+}
+
+fn storage_key(key_name: &str, derivation_path: &[Vec<u8>]) -> String {
+    let path: Vec<_> = derivation_path.iter().map(hex::encode).collect();
+    format!("{}:{}", key_name, path.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ic::IcSigner;
+    use hex_literal::hex;
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    // Reference values computed with DFINITY's `ic-secp256k1` crate.
+    const ROOT: [u8; 33] = hex!("031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f");
+    const CHILD: [u8; 33] = hex!("02afa85f9ef6828b290490f67863be5af0288d1b779c876ad1c1801540b169a0ea");
+    const CHILD_CHAIN_CODE: [u8; 32] = hex!("9ed577f6985f018817d66744feb0d0c1ff0c97016ea0b583f0ce1fd03c561a83");
+
+    fn path() -> Vec<Vec<u8>> {
+        vec![b"alice".to_vec(), vec![0, 0, 0, 7]]
+    }
+
+    fn keys() -> KeyManager {
+        let keys = KeyManager::new("test_key_1");
+        keys.insert(&[], &ROOT, &[2; 32]).unwrap();
+        keys
+    }
+
+    #[test]
+    fn should_build_derivation_paths_within_limits() {
+        let principal = Principal::from_slice(&[1, 2, 3]);
+
+        let path = DerivationPath::builder()
+            .principal(&principal)
+            .index(7)
+            .component(b"savings".to_vec())
+            .build()
+            .unwrap();
+        let too_long = (0..256).fold(DerivationPath::builder(), |builder, index| builder.index(index));
+
+        assert_eq!(
+            path.components(),
+            &[vec![1, 2, 3], vec![0, 0, 0, 7], b"savings".to_vec()][..]
+        );
+        assert_eq!(
+            DerivationPath::for_account(&principal, 7).components(),
+            &path.components()[..2]
+        );
+        assert_eq!(too_long.build(), Err(DerivationPathError::TooLong(256)));
+    }
+
+    #[test]
+    fn should_refuse_decoding_paths_that_are_too_long() {
+        let path = DerivationPath::for_account(&Principal::from_slice(&[1, 2, 3]), 7);
+        let too_long = candid::encode_one(vec![vec![0u8]; 256]).unwrap();
+
+        let decoded: DerivationPath = candid::decode_one(&candid::encode_one(&path).unwrap()).unwrap();
+
+        assert_eq!(decoded, path);
+        assert!(candid::decode_one::<DerivationPath>(&too_long).is_err());
+        assert!(serde_json::from_value::<DerivationPath>(serde_json::json!(vec![vec![0u8]; 256])).is_err());
+    }
+
+    #[test]
+    fn should_only_let_owners_use_their_paths() {
+        let alice = Principal::from_slice(&[1]);
+        let bob = Principal::from_slice(&[2]);
+
+        assert_eq!(DerivationPath::for_principal(&alice).ensure_owned_by(&alice), Ok(()));
+        assert_eq!(DerivationPath::for_account(&alice, 3).ensure_owned_by(&alice), Ok(()));
+        assert_eq!(
+            DerivationPath::for_account(&alice, 3).ensure_owned_by(&bob),
+            Err(DerivationPathError::NotOwned(bob))
+        );
+        assert_eq!(
+            DerivationPath::default().ensure_owned_by(&alice),
+            Err(DerivationPathError::NotOwned(alice))
+        );
+        assert!(IcSigner::for_owner(&alice, &DerivationPath::for_principal(&alice), "key_1").is_ok());
+        assert_eq!(
+            IcSigner::for_owner(&bob, &DerivationPath::for_principal(&alice), "key_1").map(|_| ()),
+            Err(DerivationPathError::NotOwned(bob))
+        );
+    }
+
+    #[test]
+    fn should_derive_keys_like_the_ic() {
+        let root = PublicKey::parse_compressed(&ROOT).unwrap();
+
+        let (child, chain_code) = derive_path(&root, &[2; 32], &path());
+
+        assert_eq!(child.serialize_compressed(), CHILD);
+        assert_eq!(chain_code, CHILD_CHAIN_CODE);
+    }
+
+    #[test]
+    fn should_derive_keys_from_the_longest_cached_prefix() {
+        let keys = keys();
+        let (parent, parent_chain_code) =
+            derive_child(&PublicKey::parse_compressed(&ROOT).unwrap(), &[2; 32], &path()[0]);
+        keys.insert(&path()[..1], &parent.serialize_compressed(), &parent_chain_code)
+            .unwrap();
+
+        // Without the management canister, a missing key would panic.
+        let key = futures::executor::block_on(keys.key(&path())).unwrap();
+
+        assert_eq!(key.public_key, CHILD.to_vec());
+        assert_eq!(key.chain_code, CHILD_CHAIN_CODE.to_vec());
+        assert_eq!(
+            key.address,
+            signing::public_key_address(&PublicKey::parse_compressed(&CHILD).unwrap())
+        );
+        assert_eq!(keys.len(), 3);
+    }
+
+    #[test]
+    fn should_not_derive_keys_locally_when_disabled() {
+        let keys = keys().without_local_derivation();
+
+        assert!(keys.cached(&[]).is_some());
+        assert_eq!(keys.cached(&path()), None);
+    }
+
+    #[test]
+    fn should_forget_keys_when_the_key_name_changes() {
+        let keys = keys();
+
+        keys.set_key_name("test_key_1");
+        assert_eq!(keys.len(), 1);
+        keys.set_key_name("key_1");
+        assert!(keys.is_empty());
+        assert_eq!(keys.key_name(), "key_1");
+    }
+
+    #[test]
+    fn should_survive_upgrades_in_stable_memory() {
+        let keys = keys();
+        keys.cached(&path()).unwrap();
+        let mut map = StableBTreeMap::init(DefaultMemoryImpl::default());
+
+        keys.persist(&mut map);
+        let upgraded = KeyManager::new("test_key_1").without_local_derivation();
+        upgraded.restore(&map);
+        let switched = KeyManager::new("key_1");
+        switched.restore(&map);
+
+        assert_eq!(upgraded.cached(&path()).map(|key| key.public_key), Some(CHILD.to_vec()));
+        assert!(switched.is_empty());
+    }
+}
@@ -36,6 +36,8 @@ pub mod confirm;
 pub mod contract;
 pub mod error;
 pub mod ic;
+pub mod keys;
+pub mod metering;
 pub mod signing;
 pub mod transforms;
 pub mod transports;
@@ -79,7 +81,7 @@ pub trait BatchTransport: Transport {
     type Batch: futures::Future<Output = error::Result<Vec<error::Result<rpc::Value>>>>;
 
     /// Sends a batch of prepared RPC calls.
-    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    fn send_batch<T>(&self, requests: T, options: CallOptions) -> Self::Batch
     where
         T: IntoIterator<Item = (RequestId, rpc::Call)>;
 }
@@ -123,11 +125,11 @@ where
 {
     type Batch = T::Batch;
 
-    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    fn send_batch<I>(&self, requests: I, options: CallOptions) -> Self::Batch
     where
         I: IntoIterator<Item = (RequestId, rpc::Call)>,
     {
-        (**self).send_batch(requests)
+        (**self).send_batch(requests, options)
     }
 }
 
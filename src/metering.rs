@@ -0,0 +1,531 @@
+//! Cycles accounting, budgets and rate limits per caller.
+
+use crate::{
+    error::{Error, Result, TransportError},
+    helpers,
+    ic::{self, KeyInfo},
+    rpc,
+    signing::{Signature, Signer},
+    types::{Address, H256},
+    BatchTransport, RequestId, Transport,
+};
+use candid::{CandidType, Deserialize, Principal};
+use futures::future::{self, BoxFuture, FutureExt};
+use parking_lot::Mutex;
+use std::{collections::HashMap, sync::Arc};
+
+use crate::transports::ic_http_client::CallOptions;
+
+/// Who cycles are attributed to.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Caller {
+    /// A principal, usually the caller of the current update method.
+    Principal(Principal),
+    /// An arbitrary label, e.g. a user id of the canister's own.
+    Tag(String),
+}
+
+impl Caller {
+    /// The caller of the current method.
+    pub fn current() -> Self {
+        Caller::Principal(ic_cdk::caller())
+    }
+}
+
+impl From<Principal> for Caller {
+    fn from(principal: Principal) -> Self {
+        Caller::Principal(principal)
+    }
+}
+
+impl From<&str> for Caller {
+    fn from(tag: &str) -> Self {
+        Caller::Tag(tag.to_string())
+    }
+}
+
+/// Budget and rate limit of a caller over a time window.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Length of the window in nanoseconds.
+    pub window_nanos: u64,
+    /// Cycles a caller may spend per window, unlimited when `None`.
+    pub max_cycles: Option<u128>,
+    /// Outcalls and signatures a caller may trigger per window, unlimited when `None`.
+    pub max_calls: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            window_nanos: 24 * 60 * 60 * 1_000_000_000,
+            max_cycles: None,
+            max_calls: None,
+        }
+    }
+}
+
+/// What a caller spent, in total and in the current window.
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub total_cycles: u128,
+    pub total_calls: u64,
+    /// Calls refused because they would have exceeded the limits.
+    pub rejected_calls: u64,
+    pub window_start: u64,
+    pub window_cycles: u128,
+    pub window_calls: u64,
+}
+
+impl Usage {
+    fn charge(&mut self, limits: &Limits, cycles: u128, now: u64) -> Result<()> {
+        if now.saturating_sub(self.window_start) >= limits.window_nanos {
+            self.window_start = now;
+            self.window_cycles = 0;
+            self.window_calls = 0;
+        }
+        let exceeded = match (limits.max_cycles, limits.max_calls) {
+            (Some(max_cycles), _) if self.window_cycles.saturating_add(cycles) > max_cycles => Some(format!(
+                "cycles budget of {} per window, {} already spent",
+                max_cycles, self.window_cycles
+            )),
+            (_, Some(max_calls)) if self.window_calls >= max_calls => {
+                Some(format!("rate limit of {} calls per window", max_calls))
+            }
+            _ => None,
+        };
+        if let Some(reason) = exceeded {
+            self.rejected_calls += 1;
+            return Err(Error::LimitExceeded(reason));
+        }
+        self.total_cycles = self.total_cycles.saturating_add(cycles);
+        self.total_calls += 1;
+        self.window_cycles = self.window_cycles.saturating_add(cycles);
+        self.window_calls += 1;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    limits: Limits,
+    caller_limits: HashMap<Caller, Limits>,
+    usage: HashMap<Caller, Usage>,
+}
+
+/// Records the cycles each caller spends and refuses calls exceeding their limits.
+///
+/// Cycles are charged before every outcall or signature is requested, retries and failovers
+/// included, and stay charged when it fails since the IC keeps the fee either way.
+#[derive(Debug, Clone, Default)]
+pub struct Meter {
+    state: Arc<Mutex<State>>,
+}
+
+impl Meter {
+    /// Creates new meter applying `limits` to every caller.
+    pub fn new(limits: Limits) -> Self {
+        Meter {
+            state: Arc::new(Mutex::new(State {
+                limits,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Applies `limits` to `caller` instead of the default ones.
+    pub fn set_limits(&self, caller: Caller, limits: Limits) {
+        self.state.lock().caller_limits.insert(caller, limits);
+    }
+
+    /// Charges `cycles` and one call to `caller`, failing with [`Error::LimitExceeded`] when that
+    /// would exceed its limits.
+    pub fn charge(&self, caller: &Caller, cycles: u128) -> Result<()> {
+        let mut state = self.state.lock();
+        let limits = state.caller_limits.get(caller).copied().unwrap_or(state.limits);
+        state
+            .usage
+            .entry(caller.clone())
+            .or_default()
+            .charge(&limits, cycles, helpers::now_nanos())
+    }
+
+    /// What `caller` spent so far.
+    pub fn usage(&self, caller: &Caller) -> Usage {
+        self.state.lock().usage.get(caller).cloned().unwrap_or_default()
+    }
+
+    /// Usage of every caller, e.g. to charge cycles back or to keep it in stable memory over upgrades.
+    pub fn usage_snapshot(&self) -> Vec<(Caller, Usage)> {
+        let mut usage: Vec<_> = self.state.lock().usage.clone().into_iter().collect();
+        usage.sort_by(|a, b| a.0.cmp(&b.0));
+        usage
+    }
+
+    /// Restores usage taken with [`Meter::usage_snapshot`].
+    pub fn restore_usage(&self, usage: Vec<(Caller, Usage)>) {
+        self.state.lock().usage = usage.into_iter().collect();
+    }
+
+    /// Forgets what `caller` spent, e.g. once it was charged back, and returns it.
+    pub fn reset_usage(&self, caller: &Caller) -> Usage {
+        self.state.lock().usage.remove(caller).unwrap_or_default()
+    }
+
+    /// Charger of `caller`, e.g. to attach it to `CallOptions` by hand.
+    pub fn charger(&self, caller: Caller) -> Charger {
+        Charger {
+            meter: self.clone(),
+            caller,
+        }
+    }
+
+    /// Wraps `transport`, charging its outcalls to `caller`.
+    pub fn transport<T>(&self, transport: T, caller: Caller) -> Metered<T> {
+        Metered {
+            inner: transport,
+            charger: self.charger(caller),
+        }
+    }
+
+    /// Wraps `signer`, charging its signatures to `caller`.
+    pub fn signer<S>(&self, signer: S, caller: Caller) -> Metered<S> {
+        Metered {
+            inner: signer,
+            charger: self.charger(caller),
+        }
+    }
+
+    /// Signs `message` with threshold ECDSA, charging the signing fee to `caller`.
+    pub async fn sign(&self, caller: &Caller, message: Vec<u8>, key_info: KeyInfo) -> Result<Vec<u8>> {
+        let cycles = key_info.ecdsa_sign_cycles.unwrap_or(ic::ECDSA_SIGN_CYCLES);
+        self.charge(caller, cycles as u128)?;
+        ic::ic_raw_sign(message, key_info)
+            .await
+            .map_err(|err| Error::Transport(TransportError::Message(err)))
+    }
+}
+
+/// Charges cycles to a caller of a [`Meter`] as they are spent.
+///
+/// [`Metered`] transports attach it to the `CallOptions` of every request. `ICHttp` and `EvmRpc`
+/// charge every outcall they make with it, so that retries, grown responses and failovers count
+/// against the caller's limits, while results served from a [`Cache`](crate::transports::Cache)
+/// cost nothing.
+#[derive(Debug, Clone)]
+pub struct Charger {
+    meter: Meter,
+    caller: Caller,
+}
+
+impl Charger {
+    /// The caller cycles are charged to.
+    pub fn caller(&self) -> &Caller {
+        &self.caller
+    }
+
+    /// Charges `cycles` and one call, failing with [`Error::LimitExceeded`] when that would exceed
+    /// the caller's limits.
+    pub fn charge(&self, cycles: u128) -> Result<()> {
+        self.meter.charge(&self.caller, cycles)
+    }
+}
+
+// Chargers are equal when they charge the same caller of the same meter.
+impl PartialEq for Charger {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.meter.state, &other.meter.state) && self.caller == other.caller
+    }
+}
+
+impl Eq for Charger {}
+
+/// Cycles a transport attaches to a request.
+pub trait CycleCost {
+    /// Cycles needed to send `request` with `options`.
+    fn cycle_cost(&self, request: &rpc::Request, options: &CallOptions) -> u128;
+}
+
+/// Transport charging every outcall it makes to the [`Charger`] of its `CallOptions`.
+///
+/// Only these transports can be [`Metered`], others would send requests without ever charging them.
+/// Wrappers passing `CallOptions` through charge when the transport they wrap does.
+pub trait Charging {}
+
+/// Transport or signer wrapper charging the cycles it spends to a caller.
+///
+/// [`Charging`] transports charge every outcall they make, see [`Charger`]. Signers are charged the
+/// signing fee before every signature, whatever signer they wrap.
+#[derive(Debug, Clone)]
+pub struct Metered<T> {
+    inner: T,
+    charger: Charger,
+}
+
+impl<T> Metered<T> {
+    /// The caller cycles are charged to.
+    pub fn caller(&self) -> &Caller {
+        self.charger.caller()
+    }
+
+    fn options(&self, mut options: CallOptions) -> CallOptions {
+        options.set_charger(Some(self.charger.clone()));
+        options
+    }
+}
+
+impl<T> Transport for Metered<T>
+where
+    T: Transport + Charging,
+{
+    type Out = T::Out;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call, options: CallOptions) -> Self::Out {
+        self.inner.send(id, request, self.options(options))
+    }
+
+    fn set_max_response_bytes(&mut self, v: u64) {
+        self.inner.set_max_response_bytes(v);
+    }
+}
+
+impl<T> BatchTransport for Metered<T>
+where
+    T: BatchTransport + Charging,
+{
+    type Batch = T::Batch;
+
+    fn send_batch<I>(&self, requests: I, options: CallOptions) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        self.inner.send_batch(requests, self.options(options))
+    }
+}
+
+impl<T: CycleCost> CycleCost for Metered<T> {
+    fn cycle_cost(&self, request: &rpc::Request, options: &CallOptions) -> u128 {
+        self.inner.cycle_cost(request, options)
+    }
+}
+
+impl<S: Signer> Signer for Metered<S> {
+    fn address(&self) -> BoxFuture<'static, Result<Address>> {
+        self.inner.address()
+    }
+
+    fn sign_hash(&self, hash: H256) -> BoxFuture<'static, Result<Signature>> {
+        match self.charger.charge(self.inner.sign_cycles() as u128) {
+            Ok(()) => self.inner.sign_hash(hash),
+            Err(err) => future::err(err).boxed(),
+        }
+    }
+
+    fn sign_cycles(&self) -> u64 {
+        self.inner.sign_cycles()
+    }
+
+    fn verifies_recovery_id(&self) -> bool {
+        self.inner.verifies_recovery_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        signing::LocalSigner,
+        transports::{Cache, Failover, ICHttp, MetricsRegistry, NativeOutcallBackend, RetryPolicy},
+    };
+    use ic_cdk::api::management_canister::http_request::{CanisterHttpRequestArgument, HttpResponse, TransformArgs};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn transport() -> ICHttp {
+        failing_transport("https://rpc.example.com", 0)
+    }
+
+    /// Transport failing the first `failures` outcalls with status 503.
+    fn failing_transport(url: &str, failures: usize) -> ICHttp {
+        let outcalls = AtomicUsize::new(0);
+        let backend = NativeOutcallBackend::new(move |_: &CanisterHttpRequestArgument| {
+            let status: u16 = if outcalls.fetch_add(1, Ordering::SeqCst) < failures {
+                503
+            } else {
+                200
+            };
+            HttpResponse {
+                status: status.into(),
+                headers: vec![],
+                body: br#"{"jsonrpc":"2.0","id":0,"result":"0x1"}"#.to_vec(),
+            }
+        })
+        .with_transform("transform", |args: TransformArgs| args.response);
+        ICHttp::new(url, Some(1_000)).unwrap().with_backend(backend)
+    }
+
+    fn retrying(transport: ICHttp) -> ICHttp {
+        transport.with_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            ..Default::default()
+        })
+    }
+
+    fn execute<T: Transport>(transport: &T) -> Result<rpc::Value> {
+        futures::executor::block_on(transport.execute("eth_chainId", vec![], CallOptions::default()))
+    }
+
+    #[test]
+    fn should_attribute_cycles_to_callers() {
+        let meter = Meter::default();
+        let alice = meter.transport(transport(), Caller::from("alice"));
+        let bob = meter.transport(transport(), Caller::from("bob"));
+        let cost = transport().estimate_cost("eth_chainId", vec![], &CallOptions::default());
+
+        execute(&alice).unwrap();
+        execute(&alice).unwrap();
+        execute(&bob).unwrap();
+
+        assert_eq!(meter.usage(&"alice".into()).total_cycles, 2 * cost);
+        assert_eq!(meter.usage(&"alice".into()).total_calls, 2);
+        assert_eq!(meter.usage(&"bob".into()).total_cycles, cost);
+        assert_eq!(meter.usage_snapshot().len(), 2);
+    }
+
+    #[test]
+    fn should_enforce_budgets_per_caller() {
+        let cost = transport().estimate_cost("eth_chainId", vec![], &CallOptions::default());
+        let meter = Meter::new(Limits {
+            max_cycles: Some(cost),
+            ..Default::default()
+        });
+        meter.set_limits(
+            "vip".into(),
+            Limits {
+                max_cycles: None,
+                ..Default::default()
+            },
+        );
+        let user = meter.transport(transport(), "user".into());
+        let vip = meter.transport(transport(), "vip".into());
+
+        assert!(execute(&user).is_ok());
+        assert!(matches!(execute(&user), Err(Error::LimitExceeded(_))));
+        assert!(execute(&vip).is_ok());
+        assert!(execute(&vip).is_ok());
+        assert_eq!(meter.usage(&"user".into()).rejected_calls, 1);
+    }
+
+    #[test]
+    fn should_charge_every_attempt() {
+        let cost = transport().estimate_cost("eth_chainId", vec![], &CallOptions::default());
+        let meter = Meter::default();
+        let metered = meter.transport(retrying(failing_transport("https://rpc.example.com", 2)), "user".into());
+
+        execute(&metered).unwrap();
+
+        assert_eq!(meter.usage(&"user".into()).total_cycles, 3 * cost);
+        assert_eq!(meter.usage(&"user".into()).total_calls, 3);
+    }
+
+    #[test]
+    fn should_refuse_retries_exceeding_the_budget() {
+        let cost = transport().estimate_cost("eth_chainId", vec![], &CallOptions::default());
+        let meter = Meter::new(Limits {
+            max_cycles: Some(2 * cost),
+            ..Default::default()
+        });
+        let metered = meter.transport(retrying(failing_transport("https://rpc.example.com", 2)), "user".into());
+
+        assert!(matches!(execute(&metered), Err(Error::LimitExceeded(_))));
+        assert_eq!(meter.usage(&"user".into()).total_cycles, 2 * cost);
+        assert_eq!(meter.usage(&"user".into()).rejected_calls, 1);
+    }
+
+    #[test]
+    fn should_charge_every_endpoint_tried_and_nothing_for_cached_results() {
+        let cost = transport().estimate_cost("eth_chainId", vec![], &CallOptions::default());
+        let meter = Meter::default();
+        let failover = Failover::new(vec![
+            (
+                "https://metering-down.example.com",
+                failing_transport("https://rpc.example.com", 1),
+            ),
+            ("https://metering-up.example.com", transport()),
+        ]);
+        let metrics = MetricsRegistry::default();
+        let metered = meter.transport(metrics.wrap("failover", Cache::new(failover)), "user".into());
+
+        execute(&metered).unwrap();
+        execute(&metered).unwrap();
+
+        assert_eq!(meter.usage(&"user".into()).total_cycles, 2 * cost);
+        assert_eq!(meter.usage(&"user".into()).total_calls, 2);
+    }
+
+    #[test]
+    fn should_charge_signatures_of_wrapped_signers() {
+        let meter = Meter::new(Limits {
+            max_calls: Some(1),
+            ..Default::default()
+        });
+        let signer = LocalSigner::from_slice(&[1; 32]).unwrap();
+        let metered = meter.signer(signer.clone(), "user".into());
+
+        let signature = futures::executor::block_on(metered.sign_message(b"hello")).unwrap();
+        assert_eq!(
+            signature,
+            futures::executor::block_on(signer.sign_message(b"hello")).unwrap()
+        );
+        assert!(matches!(
+            futures::executor::block_on(metered.sign_hash(H256::zero())),
+            Err(Error::LimitExceeded(_))
+        ));
+        assert_eq!(meter.usage(&"user".into()).total_calls, 1);
+        assert_eq!(meter.usage(&"user".into()).rejected_calls, 1);
+    }
+
+    #[test]
+    fn should_charge_the_signing_fee_before_signing() {
+        let meter = Meter::new(Limits {
+            max_cycles: Some(ic::ECDSA_SIGN_CYCLES as u128 - 1),
+            ..Default::default()
+        });
+        let signer = meter.signer(
+            ic::IcSigner::new(KeyInfo {
+                derivation_path: vec![],
+                key_name: "key_1".to_string(),
+                ecdsa_sign_cycles: None,
+            }),
+            "user".into(),
+        );
+
+        // Refused before the management canister is called.
+        assert!(matches!(
+            futures::executor::block_on(signer.sign_hash(H256::zero())),
+            Err(Error::LimitExceeded(_))
+        ));
+        assert_eq!(meter.usage(&"user".into()).rejected_calls, 1);
+    }
+
+    #[test]
+    fn should_enforce_rate_limits_per_window() {
+        let limits = Limits {
+            window_nanos: 1_000,
+            max_calls: Some(2),
+            ..Default::default()
+        };
+        let mut usage = Usage::default();
+
+        assert!(usage.charge(&limits, 1, 0).is_ok());
+        assert!(usage.charge(&limits, 1, 10).is_ok());
+        assert!(usage.charge(&limits, 1, 20).is_err());
+        // A new window starts.
+        assert!(usage.charge(&limits, 1, 1_000).is_ok());
+        assert_eq!(usage.total_calls, 3);
+        assert_eq!(usage.window_calls, 1);
+    }
+}
@@ -1,6 +1,12 @@
 //! Signing capabilities and utilities.
 
-use crate::types::H256;
+use crate::{
+    error,
+    types::{Address, Bytes, SignedData, H256},
+};
+use futures::future::{self, BoxFuture, FutureExt};
+use libsecp256k1::{Message, PublicKey, RecoveryId, SecretKey};
+use std::{convert::TryFrom, fmt};
 
 /// Error during signing.
 #[derive(Debug, derive_more::Display, PartialEq, Clone)]
@@ -8,6 +14,18 @@ pub enum SigningError {
     /// A message to sign is invalid. Has to be a non-zero 32-bytes slice.
     #[display(fmt = "Message has to be a non-zero 32-bytes slice.")]
     InvalidMessage,
+    /// A secret or public key is invalid.
+    #[display(fmt = "Key is invalid.")]
+    InvalidKey,
+    /// The signer returned a signature not made by its key.
+    #[display(fmt = "Signature is invalid.")]
+    InvalidSignature,
+    /// The transaction type can't be encoded.
+    #[display(fmt = "Transaction type {} is not supported.", _0)]
+    UnsupportedTransactionType(u64),
+    /// The signer could not sign, e.g. because a signer canister refused to.
+    #[display(fmt = "Signing failed: {}", _0)]
+    Failed(String),
 }
 impl std::error::Error for SigningError {}
 
@@ -173,6 +191,7 @@ impl std::error::Error for RecoveryError {}
 // }
 
 /// A struct that represents the components of a secp256k1 signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Signature {
     /// V component in electrum format with chain-id replay protection.
     pub v: u64,
@@ -182,6 +201,188 @@ pub struct Signature {
     pub s: H256,
 }
 
+/// A key able to sign Ethereum transactions and messages.
+///
+/// The IC's threshold ECDSA keys sign through [`IcSigner`](crate::ic::IcSigner) and keys held by another
+/// canister through [`RemoteSigner`](crate::ic::RemoteSigner), while [`LocalSigner`] keeps a key in
+/// memory for tests and tools running outside of the IC.
+pub trait Signer: fmt::Debug + Send + Sync {
+    /// Address of the key.
+    fn address(&self) -> BoxFuture<'static, error::Result<Address>>;
+
+    /// Sign a 32-byte hash. The `v` of the signature is the recovery id, `0` or `1`.
+    fn sign_hash(&self, hash: H256) -> BoxFuture<'static, error::Result<Signature>>;
+
+    /// Sign a message enveloped according to EIP-191, see [`hash_message`].
+    fn sign_message(&self, message: &[u8]) -> BoxFuture<'static, error::Result<Signature>> {
+        self.sign_hash(hash_message(message))
+    }
+
+    /// Sign EIP-712 typed data given its domain separator and the hash of its struct, see [`hash_typed_data`].
+    fn sign_typed_data(
+        &self,
+        domain_separator: H256,
+        struct_hash: H256,
+    ) -> BoxFuture<'static, error::Result<Signature>> {
+        self.sign_hash(hash_typed_data(domain_separator, struct_hash))
+    }
+
+    /// Cycles the canister spends on every signature, charged by [`Metered`](crate::metering::Metered) signers.
+    fn sign_cycles(&self) -> u64 {
+        0
+    }
+
+    /// Whether the signer derives the recovery id of its signatures from its own public key.
+    ///
+    /// Signatures of other signers are recovered and compared to [`Signer::address`] before use.
+    fn verifies_recovery_id(&self) -> bool {
+        false
+    }
+}
+
+/// Signer holding a secret key in memory.
+#[derive(Clone)]
+pub struct LocalSigner {
+    key: SecretKey,
+}
+
+impl LocalSigner {
+    /// Signs with `key`.
+    pub fn new(key: SecretKey) -> Self {
+        LocalSigner { key }
+    }
+
+    /// Signs with the 32-byte secret key `bytes`.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, SigningError> {
+        SecretKey::parse_slice(bytes)
+            .map(Self::new)
+            .map_err(|_| SigningError::InvalidKey)
+    }
+
+    /// Public key of the secret key.
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from_secret_key(&self.key)
+    }
+}
+
+impl fmt::Debug for LocalSigner {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("LocalSigner")
+            .field("address", &public_key_address(&self.public_key()))
+            .finish()
+    }
+}
+
+impl Signer for LocalSigner {
+    fn address(&self) -> BoxFuture<'static, error::Result<Address>> {
+        future::ok(public_key_address(&self.public_key())).boxed()
+    }
+
+    fn sign_hash(&self, hash: H256) -> BoxFuture<'static, error::Result<Signature>> {
+        let (signature, recovery_id) = libsecp256k1::sign(&Message::parse(&hash.0), &self.key);
+        let signature = signature.serialize();
+        future::ok(Signature {
+            v: recovery_id.serialize() as u64,
+            r: H256::from_slice(&signature[..32]),
+            s: H256::from_slice(&signature[32..]),
+        })
+        .boxed()
+    }
+
+    fn verifies_recovery_id(&self) -> bool {
+        true
+    }
+}
+
+/// Gets the address of a public key.
+///
+/// The public address is defined as the low 20 bytes of the keccak hash of the uncompressed public key,
+/// without its `0x04` prefix.
+pub fn public_key_address(public_key: &PublicKey) -> Address {
+    let public_key = public_key.serialize();
+    let hash = keccak256(&public_key[1..]);
+    Address::from_slice(&hash[12..])
+}
+
+/// Recovery id of the 64-byte `signature` of `hash` made by `public_key`.
+///
+/// Signers like threshold ECDSA only return `r` and `s`, so the recovery id has to be found by recovering
+/// the public key.
+pub(crate) fn recovery_id(hash: H256, signature: &[u8], public_key: &PublicKey) -> Result<u8, SigningError> {
+    let message = Message::parse(&hash.0);
+    let signature =
+        libsecp256k1::Signature::parse_standard_slice(signature).map_err(|_| SigningError::InvalidSignature)?;
+    (0..2)
+        .find(|&id| {
+            RecoveryId::parse(id)
+                .and_then(|id| libsecp256k1::recover(&message, &signature, &id))
+                .is_ok_and(|recovered| recovered == *public_key)
+        })
+        .ok_or(SigningError::InvalidSignature)
+}
+
+/// Recover the address that made the 64-byte `signature` of the 32-byte `message` hash.
+pub fn recover(message: &[u8], signature: &[u8], recovery_id: i32) -> Result<Address, RecoveryError> {
+    let message = Message::parse_slice(message).map_err(|_| RecoveryError::InvalidMessage)?;
+    let recovery_id = u8::try_from(recovery_id)
+        .ok()
+        .and_then(|id| RecoveryId::parse(id).ok())
+        .ok_or(RecoveryError::InvalidSignature)?;
+    let signature =
+        libsecp256k1::Signature::parse_standard_slice(signature).map_err(|_| RecoveryError::InvalidSignature)?;
+    let public_key =
+        libsecp256k1::recover(&message, &signature, &recovery_id).map_err(|_| RecoveryError::InvalidSignature)?;
+
+    Ok(public_key_address(&public_key))
+}
+
+/// Check that `signature` of `hash` was made by `signer`.
+///
+/// Signers report the recovery id themselves, e.g. signer canisters. A wrong one would yield a
+/// signature recovering to another address, which is refused as [`SigningError::InvalidSignature`].
+/// Signers that [verify it](Signer::verifies_recovery_id) are trusted without looking their address
+/// up again.
+pub(crate) async fn check_signature<S: Signer + ?Sized>(
+    signer: &S,
+    hash: H256,
+    signature: &Signature,
+) -> error::Result<()> {
+    if signer.verifies_recovery_id() {
+        return Ok(());
+    }
+    let mut compact = [0u8; 64];
+    compact[..32].copy_from_slice(signature.r.as_bytes());
+    compact[32..].copy_from_slice(signature.s.as_bytes());
+    let recovery_id = i32::try_from(signature.v).map_err(|_| error::Error::Signing(SigningError::InvalidSignature))?;
+    let recovered = recover(hash.as_bytes(), &compact, recovery_id);
+    if recovered.ok() != Some(signer.address().await?) {
+        return Err(error::Error::Signing(SigningError::InvalidSignature));
+    }
+    Ok(())
+}
+
+/// Wrap the `signature` of `message`, enveloped according to EIP-191, into [`SignedData`] with `v`
+/// in 'Electrum' notation, as `personal_sign` returns it.
+pub(crate) fn signed_message(message: &[u8], signature: Signature) -> Result<SignedData, SigningError> {
+    let v = match signature.v {
+        0 | 1 => signature.v as u8 + 27,
+        _ => return Err(SigningError::InvalidSignature),
+    };
+    let mut signature_bytes = Vec::with_capacity(65);
+    signature_bytes.extend_from_slice(signature.r.as_bytes());
+    signature_bytes.extend_from_slice(signature.s.as_bytes());
+    signature_bytes.push(v);
+
+    Ok(SignedData {
+        message: message.to_owned(),
+        message_hash: hash_message(message),
+        v,
+        r: signature.r,
+        s: signature.s,
+        signature: Bytes(signature_bytes),
+    })
+}
+
 /// Compute the Keccak-256 hash of input bytes.
 pub fn keccak256(bytes: &[u8]) -> [u8; 32] {
     use tiny_keccak::{Hasher, Keccak};
@@ -235,6 +436,115 @@ where
     keccak256(&eth_message).into()
 }
 
+/// Hash typed data according to EIP-712.
+///
+/// The hash is `keccak256("\x19\x01" ‖ domainSeparator ‖ hashStruct(message))`.
+pub fn hash_typed_data(domain_separator: H256, struct_hash: H256) -> H256 {
+    let mut data = Vec::with_capacity(66);
+    data.extend_from_slice(b"\x19\x01");
+    data.extend_from_slice(domain_separator.as_bytes());
+    data.extend_from_slice(struct_hash.as_bytes());
+    keccak256(&data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    fn signer() -> LocalSigner {
+        LocalSigner::from_slice(&hex!(
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn should_derive_the_address_of_a_local_key() {
+        // test vector taken from:
+        // https://web3js.readthedocs.io/en/v1.2.2/web3-eth-accounts.html#sign
+        let address = futures::executor::block_on(signer().address());
+
+        assert_eq!(address, Ok(hex!("2c7536E3605D9C16a7a3D7b1898e529396a65c23").into()));
+    }
+
+    #[test]
+    fn should_sign_messages() {
+        // test vector taken from:
+        // https://web3js.readthedocs.io/en/v1.2.2/web3-eth-accounts.html#sign
+        let signature = futures::executor::block_on(signer().sign_message(b"Some data")).unwrap();
+
+        assert_eq!(
+            signature,
+            Signature {
+                v: 1,
+                r: hex!("b91467e570a6466aa9e9876cbcd013baba02900b8979d43fe208a4a4f339f5fd").into(),
+                s: hex!("6007e74cd82e037b800186422fc2da167c747ef045e5d18a5f5d4300f8e1a029").into(),
+            }
+        );
+    }
+
+    #[test]
+    fn should_find_the_recovery_id_of_a_signature() {
+        let signer = signer();
+        let hash = hash_message(b"Some data");
+        let signature = futures::executor::block_on(signer.sign_hash(hash)).unwrap();
+        let bytes = [signature.r.as_bytes(), signature.s.as_bytes()].concat();
+
+        assert_eq!(recovery_id(hash, &bytes, &signer.public_key()), Ok(1));
+        assert_eq!(
+            recovery_id(hash_message(b"Other data"), &bytes, &signer.public_key()),
+            Err(SigningError::InvalidSignature)
+        );
+        assert_eq!(
+            recovery_id(hash, &bytes[1..], &signer.public_key()),
+            Err(SigningError::InvalidSignature)
+        );
+    }
+
+    /// Signer whose address can't be looked up.
+    #[derive(Debug)]
+    struct Unreachable(LocalSigner, bool);
+
+    impl Signer for Unreachable {
+        fn address(&self) -> BoxFuture<'static, error::Result<Address>> {
+            future::err(error::Error::Unreachable).boxed()
+        }
+
+        fn sign_hash(&self, hash: H256) -> BoxFuture<'static, error::Result<Signature>> {
+            self.0.sign_hash(hash)
+        }
+
+        fn verifies_recovery_id(&self) -> bool {
+            self.1
+        }
+    }
+
+    #[test]
+    fn should_only_look_up_addresses_of_signers_not_verifying_recovery_ids() {
+        let hash = hash_message(b"Some data");
+        let signature = futures::executor::block_on(signer().sign_hash(hash)).unwrap();
+
+        let trusted = futures::executor::block_on(check_signature(&Unreachable(signer(), true), hash, &signature));
+        let checked = futures::executor::block_on(check_signature(&Unreachable(signer(), false), hash, &signature));
+
+        assert_eq!(trusted, Ok(()));
+        assert_eq!(checked, Err(error::Error::Unreachable));
+    }
+
+    #[test]
+    fn should_hash_typed_data() {
+        // test vector taken from the `Mail` example of EIP-712.
+        let domain_separator = hex!("f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f").into();
+        let struct_hash = hex!("c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e").into();
+
+        assert_eq!(
+            hash_typed_data(domain_separator, struct_hash),
+            hex!("be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2").into()
+        );
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
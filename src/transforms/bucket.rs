@@ -0,0 +1,242 @@
+//! Rounding of volatile numeric results before consensus.
+//!
+//! Replicas send their outcalls at slightly different moments, so values like the gas price or a
+//! gas estimate often differ between them and consensus fails. Rounding them up to a bucket makes
+//! replicas agree unless the value crossed a bucket boundary in between:
+//!
+//! ```ignore
+//! registry::set_method_spec("eth_gasPrice", TransformSpec::Bucket(Precision::SignificantDigits(2)));
+//! ```
+//!
+//! Values are rounded up so that fees and gas limits never end up below what the node suggested.
+
+use super::transform::TransformProcessor;
+use candid::{CandidType, Deserialize};
+use ethereum_types::U256;
+use serde_json::Value;
+use std::convert::TryFrom;
+
+/// Methods whose results are worth bucketing.
+pub const VOLATILE_METHODS: &[&str] = &[
+    "eth_gasPrice",
+    "eth_maxPriorityFeePerGas",
+    "eth_estimateGas",
+    "eth_feeHistory",
+];
+
+/// Size of the buckets values are rounded to.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    /// Keeps this many significant decimal digits, e.g. 2 rounds `12_345` up to `13_000`.
+    SignificantDigits(u32),
+    /// Buckets of at most this many basis points of the value, rounded down to a power of two so
+    /// that boundaries don't move with the value, e.g. 100 rounds to within 1%.
+    RelativeBasisPoints(u32),
+}
+
+impl Precision {
+    /// Width of the bucket holding `value`, never decreasing as values grow.
+    pub fn width(&self, value: U256) -> U256 {
+        match *self {
+            Precision::SignificantDigits(digits) => {
+                let len = value.to_string().len() as u32;
+                U256::from(10)
+                    .checked_pow(U256::from(len.saturating_sub(digits.max(1))))
+                    .unwrap_or(U256::one())
+            }
+            Precision::RelativeBasisPoints(basis_points) => {
+                let width = value.full_mul(U256::from(basis_points)) / U256::from(10_000);
+                let width = U256::try_from(width).unwrap_or(U256::MAX);
+                if width.is_zero() {
+                    U256::one()
+                } else {
+                    U256::one() << (width.bits() - 1)
+                }
+            }
+        }
+    }
+
+    /// Rounds `value` up to the end of its bucket.
+    pub fn bucket(&self, value: U256) -> U256 {
+        let width = self.width(value);
+        let remainder = value % width;
+        if remainder.is_zero() {
+            value
+        } else {
+            value.checked_add(width - remainder).unwrap_or(value)
+        }
+    }
+
+    /// Values a result bucketed to `bucketed` may have had, both ends included.
+    pub fn range(&self, bucketed: U256) -> (U256, U256) {
+        if bucketed.is_zero() {
+            return (bucketed, bucketed);
+        }
+        // Values below the end of a bucket have the width of its lower neighbour, which is smaller
+        // than the width of the end itself when rounding up crossed a digit or power of two.
+        let width = self.width(bucketed - 1);
+        let (mut low, mut high) = (bucketed - (width - 1), bucketed);
+        // Buckets never shrink as values grow, find the lowest value still rounding up to `bucketed`.
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.bucket(mid) >= bucketed {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        (low, bucketed)
+    }
+}
+
+/// Rounds quantity results, and the fees of `eth_feeHistory` results, to buckets of the given precision.
+///
+/// Anything that isn't a quantity, such as errors and the `oldestBlock` of a fee history, is left untouched.
+#[derive(Debug, Clone, Copy)]
+pub struct BucketTransformProcessor {
+    pub precision: Precision,
+}
+
+impl BucketTransformProcessor {
+    fn quantity(&self, value: &mut Value) {
+        if let Some(number) = value
+            .as_str()
+            .and_then(|value| value.strip_prefix("0x"))
+            .and_then(|digits| U256::from_str_radix(digits, 16).ok())
+        {
+            *value = format!("{:#x}", self.precision.bucket(number)).into();
+        }
+    }
+
+    fn quantities(&self, value: &mut Value) {
+        if let Some(values) = value.as_array_mut() {
+            values.iter_mut().for_each(|value| self.quantity(value));
+        }
+    }
+}
+
+impl TransformProcessor for BucketTransformProcessor {
+    fn process_body(&self, body: &[u8]) -> Vec<u8> {
+        let mut body: Value = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(_) => return body.to_vec(),
+        };
+        match body.get_mut("result") {
+            Some(result @ Value::String(_)) => self.quantity(result),
+            Some(Value::Object(history)) => {
+                for (name, field) in history.iter_mut() {
+                    match name.as_str() {
+                        "baseFeePerGas" | "baseFeePerBlobGas" => self.quantities(field),
+                        "reward" => {
+                            if let Some(rewards) = field.as_array_mut() {
+                                rewards.iter_mut().for_each(|rewards| self.quantities(rewards));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        serde_json::to_vec(&body).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn process(precision: Precision, result: Value) -> Value {
+        let body = serde_json::to_vec(&json!({"jsonrpc": "2.0", "id": 0, "result": result})).unwrap();
+        let processed = BucketTransformProcessor { precision }.process_body(&body);
+        serde_json::from_slice::<Value>(&processed).unwrap()["result"].take()
+    }
+
+    #[test]
+    fn should_round_up_to_significant_digits() {
+        let precision = Precision::SignificantDigits(2);
+
+        assert_eq!(precision.bucket(12_345.into()), 13_000.into());
+        assert_eq!(precision.bucket(12_000.into()), 12_000.into());
+        assert_eq!(precision.bucket(99_001.into()), 100_000.into());
+        assert_eq!(precision.bucket(7.into()), 7.into());
+        assert_eq!(precision.range(13_000.into()), (12_001.into(), 13_000.into()));
+    }
+
+    #[test]
+    fn should_round_up_to_relative_precision() {
+        let precision = Precision::RelativeBasisPoints(100);
+
+        // 1% of 30 gwei is 0.3 gwei, rounded down to 2^28.
+        assert_eq!(precision.width(30_000_000_000u64.into()), (1u64 << 28).into());
+        assert_eq!(precision.bucket(30_000_000_000u64.into()), (112u64 << 28).into());
+        assert_eq!(precision.bucket(0.into()), 0.into());
+        assert_eq!(precision.bucket(U256::MAX), U256::MAX);
+    }
+
+    /// Checks that `range` of the bucket of `value` holds exactly the values rounding up to it.
+    fn check_range(precision: Precision, value: u64) {
+        let bucketed = precision.bucket(value.into());
+        let (low, high) = precision.range(bucketed);
+
+        assert!(low <= value.into() && U256::from(value) <= high);
+        assert_eq!(precision.bucket(low), bucketed);
+        assert_eq!(precision.bucket(high), bucketed);
+        assert_ne!(precision.bucket(low - 1), bucketed);
+    }
+
+    #[test]
+    fn should_report_the_range_of_buckets_crossing_a_boundary() {
+        let digits = Precision::SignificantDigits(2);
+        assert_eq!(digits.range(100_000.into()), (99_001.into(), 100_000.into()));
+        check_range(digits, 99_001);
+        check_range(digits, 12_345);
+
+        // Buckets of at most 100% are the highest power of two below the value.
+        let doubling = Precision::RelativeBasisPoints(10_000);
+        assert_eq!(
+            doubling.range((1u64 << 21).into()),
+            (((1u64 << 20) + 1).into(), (1u64 << 21).into())
+        );
+        check_range(doubling, (1 << 20) + 1);
+
+        // 1% buckets double from 2^12 to 2^13 at 819_200.
+        let percent = Precision::RelativeBasisPoints(100);
+        check_range(percent, 819_199);
+        check_range(percent, (1 << 20) + 1);
+    }
+
+    #[test]
+    fn should_agree_on_close_values() {
+        let precision = Precision::SignificantDigits(2);
+
+        // 30.000000016 and 30.5 gwei both end up as 31 gwei.
+        assert_eq!(process(precision, json!("0x6fc23ac10")), json!("0x737be7600"));
+        assert_eq!(process(precision, json!("0x719f11100")), json!("0x737be7600"));
+        assert_eq!(
+            precision.range(31_000_000_000u64.into()),
+            (30_000_000_001u64.into(), 31_000_000_000u64.into())
+        );
+    }
+
+    #[test]
+    fn should_bucket_fee_history() {
+        let history = json!({
+            "oldestBlock": "0x1b4",
+            "baseFeePerGas": ["0x3039"],
+            "gasUsedRatio": [0.5],
+            "reward": [["0x3039", "0x7"]]
+        });
+
+        assert_eq!(
+            process(Precision::SignificantDigits(2), history),
+            json!({
+                "oldestBlock": "0x1b4",
+                "baseFeePerGas": ["0x32c8"],
+                "gasUsedRatio": [0.5],
+                "reward": [["0x32c8", "0x7"]]
+            })
+        );
+    }
+}
@@ -1,2 +1,5 @@
+pub mod bucket;
+pub mod normalize;
 pub mod processors;
+pub mod registry;
 pub mod transform;
@@ -0,0 +1,587 @@
+//! Method-aware normalization of JSON-RPC results.
+//!
+//! Replicas only reach consensus on an outcall when every node's response is identical, yet
+//! nodes agreeing on the data still differ in how they print it: hex casing, leading zeros,
+//! fields only some clients return and the order of unordered lists. The processors here rewrite
+//! results into one canonical form per method without touching the data itself, so a normalized
+//! result decodes into the `types` structs exactly like the original one did.
+
+use super::transform::TransformProcessor;
+use candid::{CandidType, Deserialize};
+use ethereum_types::U256;
+use serde_json::Value;
+
+/// Quantities of a block, everything else hex being data.
+const BLOCK_QUANTITIES: &[&str] = &[
+    "number",
+    "gasUsed",
+    "gasLimit",
+    "baseFeePerGas",
+    "timestamp",
+    "difficulty",
+    "totalDifficulty",
+    "size",
+    "blobGasUsed",
+    "excessBlobGas",
+];
+
+const TRANSACTION_QUANTITIES: &[&str] = &[
+    "nonce",
+    "blockNumber",
+    "transactionIndex",
+    "value",
+    "gasPrice",
+    "gas",
+    "v",
+    "r",
+    "s",
+    "type",
+    "maxFeePerGas",
+    "maxPriorityFeePerGas",
+    "maxFeePerBlobGas",
+];
+
+/// Transaction fields some clients return and others don't, none of them decoded by
+/// [`Transaction`](crate::types::Transaction).
+const TRANSACTION_VARYING: &[&str] = &["yParity", "chainId"];
+
+const RECEIPT_QUANTITIES: &[&str] = &[
+    "transactionIndex",
+    "blockNumber",
+    "cumulativeGasUsed",
+    "gasUsed",
+    "status",
+    "type",
+    "effectiveGasPrice",
+    "blobGasUsed",
+    "blobGasPrice",
+];
+
+const LOG_QUANTITIES: &[&str] = &["blockNumber", "transactionIndex", "logIndex", "transactionLogIndex"];
+
+const WITHDRAWAL_QUANTITIES: &[&str] = &["index", "validatorIndex", "amount"];
+
+/// Shape of a JSON-RPC result, deciding how it's normalized.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Normalization {
+    /// A single quantity, e.g. the result of `eth_blockNumber` or `eth_getBalance`.
+    Quantity,
+    /// Unformatted data, e.g. the result of `eth_call` or `eth_getCode`.
+    Data,
+    /// A block with transaction hashes or objects.
+    Block,
+    /// A transaction object.
+    Transaction,
+    /// A transaction receipt.
+    Receipt,
+    /// A list of logs, or of block and transaction hashes for `eth_getFilterChanges`, in any order.
+    Logs,
+    /// The result of `eth_feeHistory`.
+    FeeHistory,
+}
+
+impl Normalization {
+    /// The normalization of results of `method`, if it's known.
+    pub fn for_method(method: &str) -> Option<Self> {
+        Some(match method {
+            "eth_blockNumber"
+            | "eth_chainId"
+            | "eth_gasPrice"
+            | "eth_maxPriorityFeePerGas"
+            | "eth_estimateGas"
+            | "eth_getBalance"
+            | "eth_getTransactionCount"
+            | "eth_getBlockTransactionCountByHash"
+            | "eth_getBlockTransactionCountByNumber"
+            | "eth_getUncleCountByBlockHash"
+            | "eth_getUncleCountByBlockNumber"
+            | "eth_newFilter"
+            | "eth_newBlockFilter"
+            | "eth_newPendingTransactionFilter" => Normalization::Quantity,
+            "eth_call" | "eth_getCode" | "eth_getStorageAt" | "eth_sendRawTransaction" | "eth_sendTransaction" => {
+                Normalization::Data
+            }
+            "eth_getBlockByNumber"
+            | "eth_getBlockByHash"
+            | "eth_getUncleByBlockHashAndIndex"
+            | "eth_getUncleByBlockNumberAndIndex" => Normalization::Block,
+            "eth_getTransactionByHash"
+            | "eth_getTransactionByBlockHashAndIndex"
+            | "eth_getTransactionByBlockNumberAndIndex" => Normalization::Transaction,
+            "eth_getTransactionReceipt" => Normalization::Receipt,
+            "eth_getLogs" | "eth_getFilterLogs" | "eth_getFilterChanges" => Normalization::Logs,
+            "eth_feeHistory" => Normalization::FeeHistory,
+            _ => return None,
+        })
+    }
+
+    /// Rewrites `result` into its canonical form.
+    pub fn normalize(&self, result: &mut Value) {
+        match self {
+            Normalization::Quantity => quantity(result),
+            Normalization::Data => data(result),
+            Normalization::Block => block(result),
+            Normalization::Transaction => object(result, TRANSACTION_QUANTITIES, TRANSACTION_VARYING),
+            Normalization::Receipt => receipt(result),
+            Normalization::Logs => logs(result),
+            Normalization::FeeHistory => fee_history(result),
+        }
+    }
+}
+
+/// Normalizes the result of a JSON-RPC response, leaving errors and bodies it can't parse untouched.
+#[derive(Debug, Clone, Copy)]
+pub struct NormalizeTransformProcessor {
+    pub normalization: Normalization,
+}
+
+impl NormalizeTransformProcessor {
+    /// Processor for results of `method`, if its normalization is known.
+    pub fn for_method(method: &str) -> Option<Self> {
+        Normalization::for_method(method).map(|normalization| NormalizeTransformProcessor { normalization })
+    }
+}
+
+impl TransformProcessor for NormalizeTransformProcessor {
+    fn process_body(&self, body: &[u8]) -> Vec<u8> {
+        let mut body: Value = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(_) => return body.to_vec(),
+        };
+        if let Some(result) = body.get_mut("result") {
+            self.normalization.normalize(result);
+        }
+        serde_json::to_vec(&body).unwrap_or_default()
+    }
+}
+
+fn is_hex(value: &str) -> bool {
+    value.starts_with("0x") && value[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Prints a quantity lowercase without leading zeros, e.g. `0x00A` as `0xa`.
+fn quantity(value: &mut Value) {
+    if let Some(canonical) = value
+        .as_str()
+        .filter(|value| value.len() > 2 && is_hex(value))
+        .and_then(|value| U256::from_str_radix(&value[2..], 16).ok())
+        .map(|number| format!("{:#x}", number))
+    {
+        *value = canonical.into();
+    }
+}
+
+/// Prints data lowercase, keeping its length.
+fn data(value: &mut Value) {
+    if let Some(canonical) = value
+        .as_str()
+        .filter(|value| is_hex(value))
+        .map(str::to_ascii_lowercase)
+    {
+        *value = canonical.into();
+    }
+}
+
+fn quantities(value: &mut Value) {
+    if let Some(values) = value.as_array_mut() {
+        values.iter_mut().for_each(quantity);
+    }
+}
+
+/// Normalizes an object whose fields are data unless listed in `quantities`, dropping `varying` ones.
+fn object(value: &mut Value, quantities: &[&str], varying: &[&str]) {
+    let fields = match value.as_object_mut() {
+        Some(fields) => fields,
+        None => return,
+    };
+    for field in varying {
+        fields.remove(*field);
+    }
+    for (name, field) in fields.iter_mut() {
+        match field {
+            Value::String(_) if quantities.contains(&name.as_str()) => quantity(field),
+            Value::String(_) => data(field),
+            Value::Array(elements) => elements.iter_mut().for_each(object_or_data),
+            Value::Object(_) => object_or_data(field),
+            _ => {}
+        }
+    }
+}
+
+/// Nested values without a known shape, e.g. access lists or topics, only hold data.
+fn object_or_data(value: &mut Value) {
+    match value {
+        Value::String(_) => data(value),
+        Value::Array(elements) => elements.iter_mut().for_each(object_or_data),
+        Value::Object(_) => object(value, &[], &[]),
+        _ => {}
+    }
+}
+
+fn block(value: &mut Value) {
+    let (transactions, withdrawals) = match value.as_object_mut() {
+        Some(fields) => (fields.remove("transactions"), fields.remove("withdrawals")),
+        None => return,
+    };
+    // Every block field is kept, even `totalDifficulty` which geth dropped after the merge, since
+    // `Block` decodes it.
+    object(value, BLOCK_QUANTITIES, &[]);
+    let fields = value.as_object_mut().expect("checked above; qed");
+    if let Some(mut transactions) = transactions {
+        if let Some(transactions) = transactions.as_array_mut() {
+            for transaction in transactions {
+                match transaction {
+                    Value::Object(_) => object(transaction, TRANSACTION_QUANTITIES, TRANSACTION_VARYING),
+                    _ => data(transaction),
+                }
+            }
+        }
+        fields.insert("transactions".into(), transactions);
+    }
+    if let Some(mut withdrawals) = withdrawals {
+        if let Some(withdrawals) = withdrawals.as_array_mut() {
+            for withdrawal in withdrawals {
+                object(withdrawal, WITHDRAWAL_QUANTITIES, &[]);
+            }
+        }
+        fields.insert("withdrawals".into(), withdrawals);
+    }
+}
+
+fn receipt(value: &mut Value) {
+    let logs = match value.as_object_mut() {
+        Some(fields) => fields.remove("logs"),
+        None => return,
+    };
+    object(value, RECEIPT_QUANTITIES, &[]);
+    if let Some(mut logs) = logs {
+        // Logs of a receipt are ordered by their index already, they're only reformatted.
+        if let Some(logs) = logs.as_array_mut() {
+            for log in logs {
+                object(log, LOG_QUANTITIES, &[]);
+            }
+        }
+        value
+            .as_object_mut()
+            .expect("checked above; qed")
+            .insert("logs".into(), logs);
+    }
+}
+
+fn quantity_of(value: &Value, field: &str) -> Option<U256> {
+    value
+        .get(field)
+        .and_then(Value::as_str)
+        .filter(|value| value.len() > 2 && is_hex(value))
+        .and_then(|value| U256::from_str_radix(&value[2..], 16).ok())
+}
+
+fn logs(value: &mut Value) {
+    let elements = match value.as_array_mut() {
+        Some(elements) => elements,
+        None => return,
+    };
+    for element in elements.iter_mut() {
+        match element {
+            Value::Object(_) => object(element, LOG_QUANTITIES, &[]),
+            _ => data(element),
+        }
+    }
+    // Hashes from block and pending transaction filters are already lowercase and compare as such.
+    elements.sort_by(|a, b| match (a.as_str(), b.as_str()) {
+        (Some(a), Some(b)) => a.cmp(b),
+        _ => (quantity_of(a, "blockNumber"), quantity_of(a, "logIndex"))
+            .cmp(&(quantity_of(b, "blockNumber"), quantity_of(b, "logIndex"))),
+    });
+}
+
+fn fee_history(value: &mut Value) {
+    let fields = match value.as_object_mut() {
+        Some(fields) => fields,
+        None => return,
+    };
+    for (name, field) in fields.iter_mut() {
+        match name.as_str() {
+            "oldestBlock" => quantity(field),
+            "baseFeePerGas" | "baseFeePerBlobGas" => quantities(field),
+            "reward" => {
+                if let Some(rewards) = field.as_array_mut() {
+                    rewards.iter_mut().for_each(quantities);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Block, FeeHistory, Log, Transaction, TransactionReceipt as Receipt, H256};
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+
+    fn normalize(normalization: Normalization, result: Value) -> Value {
+        let body = serde_json::to_vec(&json!({"jsonrpc": "2.0", "id": 0, "result": result})).unwrap();
+        let processed = NormalizeTransformProcessor { normalization }.process_body(&body);
+        serde_json::from_slice::<Value>(&processed).unwrap()["result"].take()
+    }
+
+    /// Normalizes the results of two nodes, checking they end up identical and decode like the first one.
+    fn check<T: DeserializeOwned + PartialEq + std::fmt::Debug>(
+        normalization: Normalization,
+        node_a: Value,
+        node_b: Value,
+    ) -> Value {
+        let a = normalize(normalization, node_a.clone());
+        let b = normalize(normalization, node_b);
+        assert_eq!(serde_json::to_vec(&a).unwrap(), serde_json::to_vec(&b).unwrap());
+        assert_eq!(
+            serde_json::from_value::<T>(a.clone()).unwrap(),
+            serde_json::from_value::<T>(node_a).unwrap()
+        );
+        a
+    }
+
+    #[test]
+    fn should_canonicalize_quantities_and_data() {
+        assert_eq!(normalize(Normalization::Quantity, json!("0x00A")), json!("0xa"));
+        assert_eq!(normalize(Normalization::Quantity, json!("0x0")), json!("0x0"));
+        assert_eq!(normalize(Normalization::Data, json!("0x00AB")), json!("0x00ab"));
+        // Not hex, left as is.
+        assert_eq!(normalize(Normalization::Quantity, json!("latest")), json!("latest"));
+    }
+
+    #[test]
+    fn should_keep_errors_and_invalid_bodies() {
+        let processor = NormalizeTransformProcessor {
+            normalization: Normalization::Quantity,
+        };
+        let error = br#"{"jsonrpc":"2.0","id":0,"error":{"code":-32000,"message":"0xAB"}}"#;
+
+        assert_eq!(
+            serde_json::from_slice::<Value>(&processor.process_body(error)).unwrap(),
+            serde_json::from_slice::<Value>(error).unwrap()
+        );
+        assert_eq!(processor.process_body(b"not json"), b"not json".to_vec());
+    }
+
+    #[test]
+    fn should_sort_logs_and_keep_their_indices() {
+        let log = |block: &str, index: &str, address: &str| {
+            json!({
+                "address": address,
+                "topics": ["0x59ebeb90bc63057b6515673c3ecf9438e5058bca0f92585014eced636878c9a5"],
+                "data": "0x",
+                "blockHash": "0x8216c5785ac562ff41e2dcfdf5785ac562ff41e2dcfdf829c5a142f1fccd7d00",
+                "blockNumber": block,
+                "transactionHash": "0xdf829c5a142f1fccd7d8216c5785ac562ff41e2dcfdf5785ac562ff41e2dcf00",
+                "transactionIndex": "0x3",
+                "logIndex": index,
+                "removed": false
+            })
+        };
+        let node_a = json!([
+            log("0x1b4", "0x10", "0x16c5785ac562ff41e2dcfdf829c5a142f1fccd7d"),
+            log("0x1b4", "0x11", "0x16c5785ac562ff41e2dcfdf829c5a142f1fccd7d"),
+            log("0x1b5", "0x2", "0x16c5785ac562ff41e2dcfdf829c5a142f1fccd7d"),
+        ]);
+        let node_b = json!([
+            log("0x1B5", "0x02", "0x16C5785AC562FF41E2DCFDF829C5A142F1FCCD7D"),
+            log("0x1b4", "0x011", "0x16c5785ac562ff41e2dcfdf829c5a142f1fccd7d"),
+            log("0x01b4", "0x10", "0x16C5785ac562ff41e2dcfdf829c5a142f1fccd7d"),
+        ]);
+
+        let logs = check::<Vec<Log>>(Normalization::Logs, node_a, node_b);
+
+        let indices: Vec<_> = logs
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|log| log["logIndex"].clone())
+            .collect();
+        assert_eq!(indices, vec![json!("0x10"), json!("0x11"), json!("0x2")]);
+    }
+
+    #[test]
+    fn should_sort_filter_hashes() {
+        let a = format!("{:?}", H256::from_low_u64_be(1));
+        let b = format!("{:?}", H256::from_low_u64_be(0xab));
+
+        assert_eq!(
+            normalize(Normalization::Logs, json!([b.to_uppercase().replace("0X", "0x"), a])),
+            json!([a, b])
+        );
+    }
+
+    #[test]
+    fn should_normalize_blocks_with_transactions() {
+        let transaction = |nonce: &str, from: &str, extra: Value| {
+            let mut transaction = json!({
+                "hash": "0xb903239f8543d04b5dc1ba6579132b143087c68db1b2168786408fcbce568238",
+                "nonce": nonce,
+                "blockHash": "0x8216c5785ac562ff41e2dcfdf5785ac562ff41e2dcfdf829c5a142f1fccd7d00",
+                "blockNumber": "0x1b4",
+                "transactionIndex": "0x0",
+                "from": from,
+                "to": null,
+                "value": "0x0",
+                "gasPrice": "0x4a817c800",
+                "gas": "0x5208",
+                "input": "0x",
+                "v": "0x25",
+                "r": "0x01",
+                "s": "0x2",
+                "type": "0x0"
+            });
+            transaction
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            transaction
+        };
+        let block = |number: &str, nonce: &str, transaction: Value, extra: Value| {
+            let mut block = json!({
+                "hash": "0x8216c5785ac562ff41e2dcfdf5785ac562ff41e2dcfdf829c5a142f1fccd7d00",
+                "parentHash": "0xdf829c5a142f1fccd7d8216c5785ac562ff41e2dcfdf5785ac562ff41e2dcf00",
+                "sha3Uncles": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+                "miner": "0x16c5785ac562ff41e2dcfdf829c5a142f1fccd7d",
+                "stateRoot": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+                "transactionsRoot": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+                "receiptsRoot": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+                "number": number,
+                "gasUsed": "0x5208",
+                "gasLimit": "0x1c9c380",
+                "baseFeePerGas": "0x7",
+                "extraData": "0xABCD",
+                "logsBloom": format!("0x{}", "0".repeat(512)),
+                "timestamp": "0x6543210f",
+                "difficulty": "0x0",
+                "uncles": [],
+                "transactions": [transaction],
+                "size": "0x220",
+                "mixHash": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+                "nonce": nonce,
+                "withdrawals": [{"index": "0x1", "validatorIndex": "0x2", "address": "0x16c5785ac562ff41e2dcfdf829c5a142f1fccd7d", "amount": "0x3"}]
+            });
+            block
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            block
+        };
+        let node_a = block(
+            "0x1b4",
+            "0x0000000000000000",
+            transaction("0x1", "0x16c5785ac562ff41e2dcfdf829c5a142f1fccd7d", json!({})),
+            json!({}),
+        );
+        let node_b = block(
+            "0x01B4",
+            "0x0000000000000000",
+            transaction(
+                "0x01",
+                "0x16C5785AC562FF41E2DCFDF829C5A142F1FCCD7D",
+                json!({"yParity": "0x0", "chainId": "0x1"}),
+            ),
+            json!({"extraData": "0xabcd"}),
+        );
+
+        let normalized = check::<Block<Transaction>>(Normalization::Block, node_a, node_b);
+
+        // Block nonces are data and keep their length, transaction ones are quantities.
+        assert_eq!(normalized["nonce"], json!("0x0000000000000000"));
+        assert_eq!(normalized["transactions"][0]["r"], json!("0x1"));
+        assert_eq!(normalized["withdrawals"][0]["index"], json!("0x1"));
+    }
+
+    #[test]
+    fn should_keep_block_fields_decoded_by_block() {
+        let raw = json!({
+            "hash": "0x8216c5785ac562ff41e2dcfdf5785ac562ff41e2dcfdf829c5a142f1fccd7d00",
+            "parentHash": "0xdf829c5a142f1fccd7d8216c5785ac562ff41e2dcfdf5785ac562ff41e2dcf00",
+            "sha3Uncles": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+            "miner": "0x16C5785AC562FF41E2DCFDF829C5A142F1FCCD7D",
+            "stateRoot": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+            "transactionsRoot": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+            "receiptsRoot": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+            "number": "0x01b4",
+            "gasUsed": "0x5208",
+            "gasLimit": "0x1c9c380",
+            "extraData": "0x",
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "timestamp": "0x6543210f",
+            "difficulty": "0x0",
+            "totalDifficulty": "0x0C70D815D562D3CFA955",
+            "uncles": [],
+            "transactions": [],
+            "size": "0x220",
+            "mixHash": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+            "nonce": "0x0000000000000000"
+        });
+
+        let normalized = normalize(Normalization::Block, raw.clone());
+
+        assert_eq!(normalized["totalDifficulty"], json!("0xc70d815d562d3cfa955"));
+        let block: Block<H256> = serde_json::from_value(normalized).unwrap();
+        assert_eq!(block, serde_json::from_value::<Block<H256>>(raw).unwrap());
+        assert!(block.total_difficulty.is_some());
+    }
+
+    #[test]
+    fn should_normalize_receipts() {
+        let receipt = |index: &str, status: &str, address: &str| {
+            json!({
+                "transactionHash": "0xb903239f8543d04b5dc1ba6579132b143087c68db1b2168786408fcbce568238",
+                "transactionIndex": index,
+                "blockNumber": "0xb",
+                "blockHash": "0xc6ef2fc5426d6ad6fd9e2a26abeab0aa2411b7ab17f30a99d3cb96aed1d1055b",
+                "from": "0x16c5785ac562ff41e2dcfdf829c5a142f1fccd7d",
+                "to": null,
+                "cumulativeGasUsed": "0x33bc",
+                "gasUsed": "0x4dc",
+                "contractAddress": address,
+                "logs": [{
+                    "address": address,
+                    "topics": [],
+                    "data": "0x00",
+                    "blockNumber": "0xb",
+                    "logIndex": index
+                }],
+                "logsBloom": format!("0x{}", "0".repeat(512)),
+                "status": status,
+                "effectiveGasPrice": "0x7"
+            })
+        };
+
+        let normalized = check::<Receipt>(
+            Normalization::Receipt,
+            receipt("0x10", "0x1", "0xb60e8dd61c5d32be8058bb8eb970870f07233155"),
+            receipt("0x010", "0x01", "0xB60E8DD61C5D32BE8058BB8EB970870F07233155"),
+        );
+
+        assert_eq!(normalized["transactionIndex"], json!("0x10"));
+        assert_eq!(normalized["logs"][0]["data"], json!("0x00"));
+    }
+
+    #[test]
+    fn should_normalize_fee_history() {
+        let normalized = check::<FeeHistory>(
+            Normalization::FeeHistory,
+            json!({
+                "oldestBlock": "0x1b4",
+                "baseFeePerGas": ["0x7", "0x8"],
+                "gasUsedRatio": [0.5],
+                "reward": [["0x1", "0x2"]]
+            }),
+            json!({
+                "oldestBlock": "0x01B4",
+                "baseFeePerGas": ["0x07", "0x8"],
+                "gasUsedRatio": [0.5],
+                "reward": [["0x01", "0x002"]]
+            }),
+        );
+
+        assert_eq!(normalized["reward"], json!([["0x1", "0x2"]]));
+    }
+}
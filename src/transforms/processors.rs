@@ -1,6 +1,6 @@
 use super::transform::{
-    ArrayResultTransformProcessor, ArrayResultTransformProcessorBuilder, SingleResultTransformProcessor,
-    SingleResultTransformProcessorBuilder,
+    ArrayResultTransformProcessor, ArrayResultTransformProcessorBuilder, BatchTransformProcessor,
+    SingleResultTransformProcessor, SingleResultTransformProcessorBuilder, TransformProcessor,
 };
 
 pub fn send_transaction_processor() -> SingleResultTransformProcessor {
@@ -17,3 +17,7 @@ pub fn get_filter_changes_processor() -> ArrayResultTransformProcessor {
         .build()
         .unwrap()
 }
+
+pub fn batch_processor<P: TransformProcessor>(inner: P) -> BatchTransformProcessor<P> {
+    BatchTransformProcessor { inner }
+}
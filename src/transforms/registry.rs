@@ -0,0 +1,360 @@
+//! Registry of transform processors behind a single transform endpoint.
+//!
+//! `ICHttp` encodes a [`TransformSpec`] chosen per JSON-RPC method into `TransformContext.context`,
+//! and the canister's only transform function hands its arguments to [`transform`], which decodes
+//! the spec and dispatches to the matching processor:
+//!
+//! ```ignore
+//! #[query]
+//! fn transform(args: TransformArgs) -> HttpResponse {
+//!     ic_web3_rs::transforms::registry::transform(args)
+//! }
+//! ```
+
+use super::{
+    bucket::{BucketTransformProcessor, Precision},
+    normalize::{Normalization, NormalizeTransformProcessor},
+    transform::{error_envelope, ArrayResultTransformProcessor, SingleResultTransformProcessor, TransformProcessor},
+};
+use candid::{CandidType, Deserialize};
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+use jsonrpc_core::{Call, Request};
+use serde_json::Value;
+use std::{cell::RefCell, collections::HashMap};
+
+/// Name of the canister query method `ICHttp` routes responses through.
+pub const TRANSFORM_METHOD: &str = "transform";
+
+/// Error code of the envelope returned when the transform context can't be turned into a processor.
+pub const INVALID_TRANSFORM_CODE: i64 = -32097;
+
+/// Creates a processor given the settings encoded in a [`TransformSpec::Custom`].
+pub type ProcessorFactory = fn(&[u8]) -> Box<dyn TransformProcessor>;
+
+thread_local! {
+    static CUSTOM_PROCESSORS: RefCell<HashMap<String, ProcessorFactory>> = RefCell::new(HashMap::new());
+    static METHOD_SPECS: RefCell<HashMap<String, TransformSpec>> = RefCell::new(HashMap::new());
+}
+
+/// A processor and its settings, as encoded in `TransformContext.context`.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TransformSpec {
+    /// Keeps the body as is, only dropping the headers.
+    Identity,
+    /// [`SingleResultTransformProcessor`].
+    SingleResult { transaction_index: bool },
+    /// [`ArrayResultTransformProcessor`].
+    ArrayResult { transaction_index: bool, log_index: bool },
+    /// [`NormalizeTransformProcessor`].
+    Normalize(Normalization),
+    /// [`BucketTransformProcessor`].
+    Bucket(Precision),
+    /// Batch response, each element processed with the spec of its request id.
+    Batch(Vec<(u64, TransformSpec)>),
+    /// Processor registered with [`register_processor`].
+    Custom { id: String, settings: Vec<u8> },
+}
+
+impl TransformSpec {
+    /// Bytes to put into `TransformContext.context`.
+    pub fn encode(&self) -> Vec<u8> {
+        candid::encode_one(self).expect("transform specs are candid serializable; qed")
+    }
+
+    /// Decodes `TransformContext.context`, an empty context standing for [`TransformSpec::Identity`].
+    pub fn decode(context: &[u8]) -> Result<Self, String> {
+        if context.is_empty() {
+            return Ok(TransformSpec::Identity);
+        }
+        candid::decode_one(context).map_err(|err| format!("invalid transform context: {}", err))
+    }
+
+    /// Spec used for responses to `method`: the one set with [`set_method_spec`] or the built-in default,
+    /// which normalizes results of known methods and keeps others as they are.
+    pub fn for_method(method: &str) -> Self {
+        if let Some(spec) = METHOD_SPECS.with(|specs| specs.borrow().get(method).cloned()) {
+            return spec;
+        }
+        Normalization::for_method(method)
+            .map(TransformSpec::Normalize)
+            .unwrap_or(TransformSpec::Identity)
+    }
+
+    /// Spec used for responses to `request`.
+    pub fn for_request(request: &Request) -> Self {
+        match request {
+            Request::Single(call) => Self::for_method(method_of(call)),
+            Request::Batch(calls) => TransformSpec::Batch(
+                calls
+                    .iter()
+                    .filter_map(|call| match call {
+                        Call::MethodCall(call) => match call.id {
+                            jsonrpc_core::Id::Num(id) => Some((id, Self::for_method(&call.method))),
+                            _ => None,
+                        },
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The processor this spec stands for, failing for a [`TransformSpec::Custom`] one that isn't registered.
+    pub fn processor(&self) -> Result<Box<dyn TransformProcessor>, String> {
+        Ok(match self {
+            TransformSpec::Identity => Box::new(IdentityTransformProcessor),
+            TransformSpec::SingleResult { transaction_index } => Box::new(SingleResultTransformProcessor {
+                transaction_index: *transaction_index,
+            }),
+            TransformSpec::ArrayResult {
+                transaction_index,
+                log_index,
+            } => Box::new(ArrayResultTransformProcessor {
+                transaction_index: *transaction_index,
+                log_index: *log_index,
+            }),
+            TransformSpec::Normalize(normalization) => Box::new(NormalizeTransformProcessor {
+                normalization: *normalization,
+            }),
+            TransformSpec::Bucket(precision) => Box::new(BucketTransformProcessor { precision: *precision }),
+            TransformSpec::Batch(specs) => Box::new(BatchDispatchProcessor {
+                specs: specs.iter().cloned().collect(),
+            }),
+            TransformSpec::Custom { id, settings } => {
+                let factory = CUSTOM_PROCESSORS
+                    .with(|processors| processors.borrow().get(id).copied())
+                    .ok_or_else(|| format!("transform processor {} is not registered", id))?;
+                factory(settings)
+            }
+        })
+    }
+}
+
+fn method_of(call: &Call) -> &str {
+    match call {
+        Call::MethodCall(call) => &call.method,
+        Call::Notification(call) => &call.method,
+        Call::Invalid { .. } => "",
+    }
+}
+
+/// Registers a processor usable with [`TransformSpec::Custom`] under `id`.
+pub fn register_processor(id: &str, factory: ProcessorFactory) {
+    CUSTOM_PROCESSORS.with(|processors| processors.borrow_mut().insert(id.to_string(), factory));
+}
+
+/// Processes responses to `method` with `spec` instead of the built-in default.
+pub fn set_method_spec(method: &str, spec: TransformSpec) {
+    METHOD_SPECS.with(|specs| specs.borrow_mut().insert(method.to_string(), spec));
+}
+
+/// Buckets results of the [volatile methods](super::bucket::VOLATILE_METHODS) with `precision`.
+pub fn set_volatile_precision(precision: Precision) {
+    for method in super::bucket::VOLATILE_METHODS {
+        set_method_spec(method, TransformSpec::Bucket(precision));
+    }
+}
+
+/// Precision results of `method` are rounded to, `None` when they aren't bucketed.
+pub fn method_precision(method: &str) -> Option<Precision> {
+    match TransformSpec::for_method(method) {
+        TransformSpec::Bucket(precision) => Some(precision),
+        _ => None,
+    }
+}
+
+/// The transform function: decodes the spec from the context and applies its processor.
+///
+/// Never traps: a context it can't turn into a processor yields an [`INVALID_TRANSFORM_CODE`] error.
+pub fn transform(args: TransformArgs) -> HttpResponse {
+    match TransformSpec::decode(&args.context).and_then(|spec| spec.processor()) {
+        Ok(processor) => processor.transform(args),
+        Err(err) => HttpResponse {
+            status: 200u8.into(),
+            headers: vec![],
+            body: error_envelope(INVALID_TRANSFORM_CODE, &err, None),
+        },
+    }
+}
+
+/// Keeps the body as is.
+#[derive(Debug, Default)]
+pub struct IdentityTransformProcessor;
+
+impl TransformProcessor for IdentityTransformProcessor {
+    fn process_body(&self, body: &[u8]) -> Vec<u8> {
+        body.to_vec()
+    }
+}
+
+/// Processes each element of a batch response with the spec of its request id.
+struct BatchDispatchProcessor {
+    specs: HashMap<u64, TransformSpec>,
+}
+
+impl TransformProcessor for BatchDispatchProcessor {
+    fn process_body(&self, body: &[u8]) -> Vec<u8> {
+        let mut elements = match serde_json::from_slice(body) {
+            Ok(Value::Array(elements)) => elements,
+            _ => return body.to_vec(),
+        };
+        elements.sort_by_key(|element| element.get("id").and_then(Value::as_u64));
+        for element in elements.iter_mut().filter(|element| element.get("result").is_some()) {
+            let spec = element
+                .get("id")
+                .and_then(Value::as_u64)
+                .and_then(|id| self.specs.get(&id))
+                .unwrap_or(&TransformSpec::Identity);
+            let processed = match spec.processor() {
+                Ok(processor) => {
+                    serde_json::from_slice(&processor.process_body(&serde_json::to_vec(element).unwrap_or_default()))
+                        .ok()
+                }
+                Err(err) => serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": element["id"],
+                    "error": {"code": INVALID_TRANSFORM_CODE, "message": err},
+                })
+                .into(),
+            };
+            if let Some(processed) = processed {
+                *element = processed;
+            }
+        }
+        serde_json::to_vec(&elements).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::build_request;
+
+    fn args(body: &str, spec: &TransformSpec) -> TransformArgs {
+        TransformArgs {
+            response: HttpResponse {
+                status: 200u8.into(),
+                headers: vec![],
+                body: body.as_bytes().to_vec(),
+            },
+            context: spec.encode(),
+        }
+    }
+
+    #[test]
+    fn should_round_trip_specs() {
+        let spec = TransformSpec::Batch(vec![
+            (0, TransformSpec::Identity),
+            (
+                1,
+                TransformSpec::ArrayResult {
+                    transaction_index: true,
+                    log_index: false,
+                },
+            ),
+        ]);
+
+        assert_eq!(TransformSpec::decode(&spec.encode()), Ok(spec));
+        assert_eq!(TransformSpec::decode(&[]), Ok(TransformSpec::Identity));
+    }
+
+    #[test]
+    fn should_pick_specs_per_method() {
+        let request = Request::Batch(vec![
+            build_request(0, "eth_blockNumber", vec![]),
+            build_request(1, "eth_getLogs", vec![]),
+            build_request(2, "net_version", vec![]),
+        ]);
+
+        assert_eq!(
+            TransformSpec::for_request(&request),
+            TransformSpec::Batch(vec![
+                (0, TransformSpec::Normalize(Normalization::Quantity)),
+                (1, TransformSpec::Normalize(Normalization::Logs)),
+                (2, TransformSpec::Identity),
+            ])
+        );
+    }
+
+    #[test]
+    fn should_dispatch_batch_elements_by_id() {
+        let spec = TransformSpec::for_request(&Request::Batch(vec![
+            build_request(0, "eth_blockNumber", vec![]),
+            build_request(1, "eth_getLogs", vec![]),
+        ]));
+        let body = r#"[
+            {"jsonrpc":"2.0","id":1,"result":[{"logIndex":"0x05","transactionIndex":"0x3"}]},
+            {"jsonrpc":"2.0","id":0,"result":"0x010"}
+        ]"#;
+
+        let response = transform(args(body, &spec));
+
+        assert_eq!(
+            serde_json::from_slice::<Value>(&response.body).unwrap(),
+            serde_json::json!([
+                {"jsonrpc": "2.0", "id": 0, "result": "0x10"},
+                {"jsonrpc": "2.0", "id": 1, "result": [{"logIndex": "0x5", "transactionIndex": "0x3"}]}
+            ])
+        );
+    }
+
+    #[test]
+    fn should_dispatch_to_custom_processors() {
+        struct Constant(Vec<u8>);
+        impl TransformProcessor for Constant {
+            fn process_body(&self, _body: &[u8]) -> Vec<u8> {
+                self.0.clone()
+            }
+        }
+        register_processor("constant", |settings| Box::new(Constant(settings.to_vec())));
+        set_method_spec(
+            "eth_gasPrice",
+            TransformSpec::Custom {
+                id: "constant".into(),
+                settings: b"42".to_vec(),
+            },
+        );
+
+        let response = transform(args(
+            r#"{"jsonrpc":"2.0","id":0,"result":"0x1"}"#,
+            &TransformSpec::for_method("eth_gasPrice"),
+        ));
+
+        assert_eq!(response.body, b"42".to_vec());
+    }
+
+    #[test]
+    fn should_not_trap_on_invalid_contexts() {
+        let mut invalid = args("{}", &TransformSpec::Identity);
+        invalid.context = b"not candid".to_vec();
+        let unregistered = args(
+            "{}",
+            &TransformSpec::Custom {
+                id: "unregistered".into(),
+                settings: vec![],
+            },
+        );
+
+        for args in [invalid, unregistered] {
+            let body: Value = serde_json::from_slice(&transform(args).body).unwrap();
+            assert_eq!(body["error"]["code"], Value::from(INVALID_TRANSFORM_CODE));
+        }
+    }
+
+    #[test]
+    fn should_bucket_volatile_methods() {
+        assert_eq!(method_precision("eth_gasPrice"), None);
+
+        set_volatile_precision(Precision::SignificantDigits(2));
+        let response = transform(args(
+            r#"{"jsonrpc":"2.0","id":0,"result":"0x3039"}"#,
+            &TransformSpec::for_method("eth_estimateGas"),
+        ));
+
+        assert_eq!(method_precision("eth_gasPrice"), Some(Precision::SignificantDigits(2)));
+        assert_eq!(
+            serde_json::from_slice::<Value>(&response.body).unwrap()["result"],
+            Value::from("0x32c8")
+        );
+    }
+}
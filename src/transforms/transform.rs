@@ -2,6 +2,7 @@ use candid::Nat;
 use derive_builder::Builder;
 use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
 use serde_json::Value;
+use std::convert::TryFrom;
 
 #[derive(Debug, Builder, Default)]
 pub struct SingleResultTransformProcessor {
@@ -14,55 +15,179 @@ pub struct ArrayResultTransformProcessor {
     pub log_index: bool,
 }
 
+/// Applies a processor to every element of a JSON-RPC batch response.
+///
+/// Nodes may answer a batch in any order, so elements are sorted by request id first. Elements
+/// carrying an error instead of a result are left untouched. Single responses are handed to the
+/// inner processor as is.
+#[derive(Debug, Default)]
+pub struct BatchTransformProcessor<P> {
+    pub inner: P,
+}
+
+/// Error code of the envelope replacing responses with an HTTP status other than 200, the status
+/// being the error data.
+pub const HTTP_ERROR_CODE: i64 = -32099;
+
+/// Error code of the envelope replacing bodies which aren't JSON.
+pub const INVALID_RESPONSE_CODE: i64 = -32098;
+
+/// Messages nodes embed in longer, node-specific ones, e.g. `nonce too low: next nonce 5, tx nonce 3`.
+const KNOWN_ERROR_MESSAGES: &[&str] = &[
+    "nonce too low",
+    "nonce too high",
+    "already known",
+    "replacement transaction underpriced",
+    "transaction underpriced",
+    "insufficient funds",
+    "intrinsic gas too low",
+    "exceeds block gas limit",
+    "execution reverted",
+    "header not found",
+    "missing trie node",
+    "filter not found",
+];
+
+/// Turns an HTTP response into one every replica agrees on.
+///
+/// Transforms never trap: bodies with an HTTP status other than 200 and bodies which aren't JSON
+/// are replaced with a JSON-RPC error envelope, and JSON-RPC errors are reduced to their code and
+/// a canonical message, so that failures reach consensus like results do and surface as
+/// [`Error::Rpc`](crate::Error::Rpc).
 pub trait TransformProcessor {
     fn transform(&self, raw: TransformArgs) -> HttpResponse {
-        let mut res = HttpResponse {
-            status: raw.response.status.clone(),
-            ..Default::default()
-        };
-        if res.status == Nat::from(200u8) {
-            res.body = self.process_body(&raw.response.body);
+        let body = if raw.response.status != Nat::from(200u8) {
+            error_envelope(
+                HTTP_ERROR_CODE,
+                &format!("HTTP status {}", raw.response.status.0),
+                Some(Value::from(u64::try_from(&raw.response.status.0).unwrap_or(u64::MAX))),
+            )
+        } else if serde_json::from_slice::<Value>(&raw.response.body).is_err() {
+            error_envelope(INVALID_RESPONSE_CODE, "Invalid JSON-RPC response", None)
         } else {
-            ic_cdk::api::print(format!("Received an error from blockchain: err = {:?}", raw));
+            canonicalize_errors(&self.process_body(&raw.response.body))
+        };
+        HttpResponse {
+            status: Nat::from(200u8),
+            headers: vec![],
+            body,
         }
-        res
     }
     fn process_body(&self, body: &[u8]) -> Vec<u8>;
 }
 
+/// A JSON-RPC response without id carrying the given error.
+pub fn error_envelope(code: i64, message: &str, data: Option<Value>) -> Vec<u8> {
+    let mut error = serde_json::json!({"code": code, "message": message});
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+    serde_json::to_vec(&serde_json::json!({"jsonrpc": "2.0", "id": null, "error": error})).unwrap_or_default()
+}
+
+/// Reduces the errors of a single or batch response to their canonical form.
+pub fn canonicalize_errors(body: &[u8]) -> Vec<u8> {
+    let mut body: Value = match serde_json::from_slice(body) {
+        Ok(body) => body,
+        Err(_) => return error_envelope(INVALID_RESPONSE_CODE, "Invalid JSON-RPC response", None),
+    };
+    match &mut body {
+        Value::Array(elements) => elements
+            .iter_mut()
+            .filter_map(|element| element.get_mut("error"))
+            .for_each(canonicalize_error),
+        body => {
+            if let Some(error) = body.get_mut("error") {
+                canonicalize_error(error)
+            }
+        }
+    }
+    serde_json::to_vec(&body).unwrap_or_default()
+}
+
+/// Keeps the code of a JSON-RPC error, a canonical message and hex data such as revert reasons,
+/// dropping the text nodes phrase differently.
+pub fn canonicalize_error(error: &mut Value) {
+    let code = error.get("code").and_then(Value::as_i64).unwrap_or_default();
+    let message = error.get("message").and_then(Value::as_str).unwrap_or_default();
+    let data = error
+        .get("data")
+        .and_then(Value::as_str)
+        .filter(|data| data.starts_with("0x") && data[2..].chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_ascii_lowercase);
+    let mut canonical = serde_json::json!({"code": code, "message": canonical_message(code, message)});
+    if let Some(data) = data {
+        canonical["data"] = data.into();
+    }
+    *error = canonical;
+}
+
+fn canonical_message(code: i64, message: &str) -> String {
+    let lowercase = message.to_lowercase();
+    if let Some(known) = KNOWN_ERROR_MESSAGES.iter().find(|known| lowercase.contains(*known)) {
+        return known.to_string();
+    }
+    match code {
+        -32700..=-32600 => jsonrpc_core::ErrorCode::from(code).description(),
+        _ => message.split(':').next().unwrap_or_default().trim().to_string(),
+    }
+}
+
 impl TransformProcessor for ArrayResultTransformProcessor {
     fn process_body(&self, body: &[u8]) -> Vec<u8> {
-        let mut body: Value = serde_json::from_slice(body).unwrap();
-        let elements = body.get_mut("result").unwrap().as_array_mut().unwrap();
-        for element in elements.iter_mut() {
+        let mut body: Value = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(_) => return body.to_vec(),
+        };
+        let elements = body
+            .get_mut("result")
+            .and_then(Value::as_array_mut)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_object_mut);
+        for element in elements {
             if self.transaction_index {
-                element
-                    .as_object_mut()
-                    .unwrap()
-                    .insert("transactionIndex".to_string(), Value::from("0x0"));
+                element.insert("transactionIndex".to_string(), Value::from("0x0"));
             }
             if self.log_index {
-                element
-                    .as_object_mut()
-                    .unwrap()
-                    .insert("logIndex".to_string(), Value::from("0x0"));
+                element.insert("logIndex".to_string(), Value::from("0x0"));
             }
         }
-        serde_json::to_vec(&body).unwrap()
+        serde_json::to_vec(&body).unwrap_or_default()
+    }
+}
+
+impl<P: TransformProcessor> TransformProcessor for BatchTransformProcessor<P> {
+    fn process_body(&self, body: &[u8]) -> Vec<u8> {
+        let mut elements = match serde_json::from_slice(body) {
+            Ok(Value::Array(elements)) => elements,
+            _ => return self.inner.process_body(body),
+        };
+        elements.sort_by_key(|element| element.get("id").and_then(Value::as_u64));
+        for element in elements.iter_mut().filter(|element| element.get("result").is_some()) {
+            let processed = self
+                .inner
+                .process_body(&serde_json::to_vec(element).unwrap_or_default());
+            if let Ok(processed) = serde_json::from_slice(&processed) {
+                *element = processed;
+            }
+        }
+        serde_json::to_vec(&elements).unwrap_or_default()
     }
 }
 
 impl TransformProcessor for SingleResultTransformProcessor {
     fn process_body(&self, body: &[u8]) -> Vec<u8> {
-        let mut body: Value = serde_json::from_slice(body).unwrap();
-        if self.transaction_index {
-            body.get_mut("result")
-                .unwrap()
-                .as_object_mut()
-                .unwrap()
-                .insert("transactionIndex".to_string(), Value::from("0x0"));
+        let mut body: Value = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(_) => return body.to_vec(),
+        };
+        if let Some(result) = body.get_mut("result").and_then(Value::as_object_mut) {
+            if self.transaction_index {
+                result.insert("transactionIndex".to_string(), Value::from("0x0"));
+            }
         }
-        serde_json::to_vec(&body).unwrap()
+        serde_json::to_vec(&body).unwrap_or_default()
     }
 }
 
@@ -74,7 +199,8 @@ pub mod tests {
     use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
 
     use crate::transforms::transform::{
-        ArrayResultTransformProcessor, SingleResultTransformProcessor, TransformProcessor,
+        ArrayResultTransformProcessor, BatchTransformProcessor, SingleResultTransformProcessor, TransformProcessor,
+        HTTP_ERROR_CODE, INVALID_RESPONSE_CODE,
     };
 
     #[test]
@@ -219,4 +345,125 @@ pub mod tests {
             assert_eq!(got_json, want_json);
         }
     }
+
+    #[test]
+    fn test_batch_transform() {
+        let input = r#"[
+            {"id":2, "jsonrpc":"2.0", "error": {"code": -32000, "message": "nonce too low"}},
+            {"id":1, "jsonrpc":"2.0", "result": {"transactionHash": "0x01", "transactionIndex": "0x10"}}
+        ]"#;
+        let want = r#"[
+            {"id":1, "jsonrpc":"2.0", "result": {"transactionHash": "0x01", "transactionIndex": "0x0"}},
+            {"id":2, "jsonrpc":"2.0", "error": {"code": -32000, "message": "nonce too low"}}
+        ]"#;
+        let args = TransformArgs {
+            response: HttpResponse {
+                status: Nat::from(200u8),
+                headers: Vec::default(),
+                body: input.as_bytes().to_vec(),
+            },
+            context: vec![],
+        };
+        let got = BatchTransformProcessor {
+            inner: SingleResultTransformProcessor {
+                transaction_index: true,
+            },
+        }
+        .transform(args);
+        let want_json = serde_json::from_str::<serde_json::Value>(want).unwrap();
+        let got_json = serde_json::from_slice::<serde_json::Value>(&got.body).unwrap();
+        assert_eq!(got_json, want_json);
+    }
+
+    fn transform_with<P: TransformProcessor>(processor: P, status: u16, body: &str) -> serde_json::Value {
+        let got = processor.transform(TransformArgs {
+            response: HttpResponse {
+                status: Nat::from(status),
+                headers: vec![],
+                body: body.as_bytes().to_vec(),
+            },
+            context: vec![],
+        });
+        assert_eq!(got.status, Nat::from(200u8));
+        serde_json::from_slice(&got.body).unwrap()
+    }
+
+    #[test]
+    fn test_http_errors_become_envelopes() {
+        let processor = || SingleResultTransformProcessor {
+            transaction_index: true,
+        };
+
+        assert_eq!(
+            transform_with(processor(), 503, "<html>Service Unavailable, ray id 1234</html>"),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": {"code": HTTP_ERROR_CODE, "message": "HTTP status 503", "data": 503}
+            })
+        );
+        assert_eq!(
+            transform_with(processor(), 200, "<html>Bad gateway</html>"),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": {"code": INVALID_RESPONSE_CODE, "message": "Invalid JSON-RPC response"}
+            })
+        );
+    }
+
+    #[test]
+    fn test_rpc_errors_reach_consensus() {
+        let geth =
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"nonce too low: next nonce 5, tx nonce 3"}}"#;
+        let erigon =
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"Nonce too low","data":{"node":"erigon"}}}"#;
+        let revert = r#"{"jsonrpc":"2.0","id":1,"error":{"code":3,"message":"execution reverted: Ownable: caller is not the owner","data":"0x08C379A0"}}"#;
+        let array = || ArrayResultTransformProcessor {
+            transaction_index: true,
+            log_index: true,
+        };
+
+        assert_eq!(transform_with(array(), 200, geth), transform_with(array(), 200, erigon));
+        assert_eq!(
+            transform_with(array(), 200, geth)["error"],
+            serde_json::json!({"code": -32000, "message": "nonce too low"})
+        );
+        assert_eq!(
+            transform_with(array(), 200, revert)["error"],
+            serde_json::json!({"code": 3, "message": "execution reverted", "data": "0x08c379a0"})
+        );
+        assert_eq!(
+            transform_with(
+                array(),
+                200,
+                r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"invalid argument 0: hex string has length 3"}}"#
+            )["error"],
+            serde_json::json!({"code": -32602, "message": "Invalid params"})
+        );
+    }
+
+    #[test]
+    fn test_batch_errors_reach_consensus() {
+        let body = r#"[
+            {"id":2, "jsonrpc":"2.0", "error": {"code": -32000, "message": "insufficient funds for gas * price + value: address 0x01 have 0 want 1"}},
+            {"id":1, "jsonrpc":"2.0", "result": {"transactionIndex": "0x10"}}
+        ]"#;
+
+        assert_eq!(
+            transform_with(
+                BatchTransformProcessor {
+                    inner: SingleResultTransformProcessor {
+                        transaction_index: true,
+                    },
+                },
+                200,
+                body
+            ),
+            serde_json::json!([
+                {"id": 1, "jsonrpc": "2.0", "result": {"transactionIndex": "0x0"}},
+                {"id": 2, "jsonrpc": "2.0", "error": {"code": -32000, "message": "insufficient funds"}}
+            ])
+        );
+    }
 }
@@ -23,7 +23,7 @@ type PendingRequests = Arc<Mutex<BTreeMap<RequestId, Pending>>>;
 pub struct Batch<T> {
     transport: T,
     pending: PendingRequests,
-    batch: Arc<Mutex<Vec<(RequestId, rpc::Call)>>>,
+    batch: Arc<Mutex<Vec<(RequestId, rpc::Call, CallOptions)>>>,
 }
 
 impl<T> Batch<T>
@@ -40,11 +40,35 @@ where
     }
 
     /// Sends all requests as a batch.
+    ///
+    /// The outcall options are combined from the options each queued call was made with.
     pub fn submit_batch(&self) -> impl Future<Output = error::Result<Vec<error::Result<rpc::Value>>>> {
         let batch = std::mem::take(&mut *self.batch.lock());
+        let (requests, options): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .map(|(id, request, options)| ((id, request), options))
+            .unzip();
+        self.send_requests(requests, CallOptions::combine(options))
+    }
+
+    /// Sends all requests as a batch, using the given outcall options.
+    pub fn submit_batch_with_options(
+        &self,
+        options: CallOptions,
+    ) -> impl Future<Output = error::Result<Vec<error::Result<rpc::Value>>>> {
+        let batch = std::mem::take(&mut *self.batch.lock());
+        let requests = batch.into_iter().map(|(id, request, _)| (id, request)).collect();
+        self.send_requests(requests, options)
+    }
+
+    fn send_requests(
+        &self,
+        batch: Vec<(RequestId, rpc::Call)>,
+        options: CallOptions,
+    ) -> impl Future<Output = error::Result<Vec<error::Result<rpc::Value>>>> {
         let ids = batch.iter().map(|&(id, _)| id).collect::<Vec<_>>();
 
-        let batch = self.transport.send_batch(batch);
+        let batch = self.transport.send_batch(batch, options);
         let pending = self.pending.clone();
 
         async move {
@@ -78,7 +102,7 @@ where
     fn send(&self, id: RequestId, request: rpc::Call, options: CallOptions) -> Self::Out {
         let (tx, rx) = oneshot::channel();
         self.pending.lock().insert(id, tx);
-        self.batch.lock().push((id, request));
+        self.batch.lock().push((id, request, options));
 
         SingleResult(rx)
     }
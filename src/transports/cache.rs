@@ -0,0 +1,512 @@
+//! Caching Transport
+
+use crate::{
+    error::{Error, Result},
+    helpers,
+    metering::{Charging, CycleCost},
+    rpc,
+    types::U64,
+    BatchTransport, RequestId, Transport,
+};
+use futures::future::{self, BoxFuture, FutureExt};
+use ic_stable_structures::{storable::Bound, Memory, StableBTreeMap, Storable};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use super::ic_http_client::CallOptions;
+
+/// Number of results kept unless configured otherwise.
+pub const DEFAULT_CAPACITY: usize = 1_000;
+
+/// Blocks a receipt must be buried under before it's considered final, unless configured otherwise.
+pub const DEFAULT_FINALITY_DEPTH: u64 = 64;
+
+/// A cached result and when it was last used.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedEntry {
+    pub last_used: u64,
+    pub value: rpc::Value,
+}
+
+impl Storable for CachedEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_json::to_vec(self).expect("entries are serializable; qed"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_json::from_slice(&bytes).expect("entries are written by to_bytes; qed")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Least recently used results, bounded by `capacity`.
+#[derive(Debug)]
+struct Lru {
+    capacity: usize,
+    entries: HashMap<String, CachedEntry>,
+    order: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Lru {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<rpc::Value> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&entry.last_used);
+        self.order.insert(self.tick, key.to_string());
+        entry.last_used = self.tick;
+        Some(entry.value.clone())
+    }
+
+    fn insert(&mut self, key: String, entry: CachedEntry) {
+        if let Some(previous) = self.entries.remove(&key) {
+            self.order.remove(&previous.last_used);
+        }
+        while !self.entries.is_empty() && self.entries.len() >= self.capacity {
+            let (_, evicted) = self.order.pop_first().expect("order indexes every entry; qed");
+            self.entries.remove(&evicted);
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        self.order.insert(entry.last_used, key.clone());
+        self.entries.insert(key, entry);
+    }
+
+    fn put(&mut self, key: String, value: rpc::Value) {
+        self.tick += 1;
+        let last_used = self.tick;
+        self.insert(key, CachedEntry { last_used, value });
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    lru: Lru,
+    finality_depth: u64,
+    finalized: Option<u64>,
+}
+
+impl State {
+    /// Caches `result` when it can never change and learns the finalized block from it.
+    fn observe(&mut self, method: &str, params: &rpc::Value, result: &rpc::Value) {
+        match method {
+            "eth_blockNumber" => {
+                if let Ok(head) = serde_json::from_value::<U64>(result.clone()) {
+                    self.finalize(head.as_u64().saturating_sub(self.finality_depth));
+                }
+            }
+            "eth_getBlockByNumber" if params[0] == "finalized" => {
+                if let Some(number) = block_number(result) {
+                    self.finalize(number);
+                }
+            }
+            _ => {}
+        }
+        if self.is_immutable(method, result) {
+            self.lru.put(cache_key(method, params), result.clone());
+        }
+    }
+
+    fn finalize(&mut self, number: u64) {
+        self.finalized = Some(self.finalized.map_or(number, |finalized| finalized.max(number)));
+    }
+
+    fn is_immutable(&self, method: &str, result: &rpc::Value) -> bool {
+        match method {
+            "eth_chainId" | "net_version" => true,
+            "eth_getBlockByHash" => !result.is_null(),
+            "eth_getTransactionReceipt" => match (block_number(result), self.finalized) {
+                (Some(number), Some(finalized)) => number <= finalized,
+                _ => false,
+            },
+            "eth_getCode" => result.as_str().is_some_and(|code| code != "0x"),
+            _ => false,
+        }
+    }
+}
+
+/// Methods whose results may be cached.
+fn is_cached_method(method: &str) -> bool {
+    matches!(
+        method,
+        "eth_chainId" | "net_version" | "eth_getBlockByHash" | "eth_getTransactionReceipt" | "eth_getCode"
+    )
+}
+
+fn cache_key(method: &str, params: &rpc::Value) -> String {
+    format!("{}:{}", method, params)
+}
+
+fn block_number(value: &rpc::Value) -> Option<u64> {
+    serde_json::from_value::<U64>(value.get("blockNumber").or_else(|| value.get("number"))?.clone())
+        .ok()
+        .map(|number| number.as_u64())
+}
+
+/// Transport wrapper answering repeated calls with immutable results without an outcall.
+///
+/// Cached are the chain id, blocks by hash, non-empty contract code and receipts of finalized
+/// transactions. A receipt is final once its block is `finality_depth` blocks below the latest
+/// `eth_blockNumber` seen, at or below the `finalized` block seen, or at or below the block set
+/// with [`Cache::set_finalized_block`].
+///
+/// Results live on the heap, [`Cache::persist`] and [`Cache::restore`] carry them over upgrades
+/// in a `StableBTreeMap`.
+#[derive(Debug, Clone)]
+pub struct Cache<T> {
+    transport: T,
+    state: Arc<Mutex<State>>,
+}
+
+impl<T: Transport> Cache<T> {
+    /// Creates new cache keeping up to [`DEFAULT_CAPACITY`] results of `transport`.
+    pub fn new(transport: T) -> Self {
+        Self::with_capacity(transport, DEFAULT_CAPACITY)
+    }
+
+    /// Creates new cache keeping up to `capacity` results of `transport`.
+    pub fn with_capacity(transport: T, capacity: usize) -> Self {
+        Cache {
+            transport,
+            state: Arc::new(Mutex::new(State {
+                lru: Lru::new(capacity),
+                finality_depth: DEFAULT_FINALITY_DEPTH,
+                finalized: None,
+            })),
+        }
+    }
+
+    /// Consider receipts final once their block is `depth` blocks below the latest block.
+    pub fn with_finality_depth(self, depth: u64) -> Self {
+        self.state.lock().finality_depth = depth;
+        self
+    }
+
+    /// Consider receipts of blocks up to `number` final.
+    pub fn set_finalized_block(&self, number: u64) {
+        self.state.lock().finalize(number);
+    }
+
+    /// Number of cached results.
+    pub fn len(&self) -> usize {
+        self.state.lock().lru.entries.len()
+    }
+
+    /// Whether nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops every cached result.
+    pub fn clear(&self) {
+        let mut state = self.state.lock();
+        let capacity = state.lru.capacity;
+        state.lru = Lru::new(capacity);
+    }
+
+    /// Writes the cached results to `map`, replacing its content. Call it in `pre_upgrade`.
+    pub fn persist<M: Memory>(&self, map: &mut StableBTreeMap<String, CachedEntry, M>) {
+        map.clear_new();
+        for (key, entry) in self.state.lock().lru.entries.iter() {
+            map.insert(key.clone(), entry.clone());
+        }
+    }
+
+    /// Loads results written by [`Cache::persist`], keeping the most recently used ones when they
+    /// don't all fit. Call it in `post_upgrade`.
+    pub fn restore<M: Memory>(&self, map: &StableBTreeMap<String, CachedEntry, M>) {
+        let mut entries: Vec<_> = map.iter().collect();
+        entries.sort_by_key(|(_, entry)| entry.last_used);
+        let mut state = self.state.lock();
+        // Restored results are renumbered after the ones already cached, in their original order.
+        for (key, entry) in entries {
+            state.lru.put(key, entry.value);
+        }
+    }
+
+    fn lookup(&self, call: &rpc::Call) -> Option<rpc::Value> {
+        let (method, params) = helpers::method_and_params(call);
+        if !is_cached_method(&method) {
+            return None;
+        }
+        self.state.lock().lru.get(&cache_key(&method, &params))
+    }
+
+    /// Whether `call` would be answered from the cache, without marking it as used.
+    fn is_cached(&self, call: &rpc::Call) -> bool {
+        let (method, params) = helpers::method_and_params(call);
+        is_cached_method(&method) && self.state.lock().lru.entries.contains_key(&cache_key(&method, &params))
+    }
+}
+
+// Results served from the cache cost nothing, batches are charged for the calls they miss.
+impl<T: Transport + CycleCost> CycleCost for Cache<T> {
+    fn cycle_cost(&self, request: &rpc::Request, options: &CallOptions) -> u128 {
+        match request {
+            rpc::Request::Single(call) if self.is_cached(call) => 0,
+            rpc::Request::Single(_) => self.transport.cycle_cost(request, options),
+            rpc::Request::Batch(calls) => {
+                let missing: Vec<_> = calls.iter().filter(|call| !self.is_cached(call)).cloned().collect();
+                if missing.is_empty() {
+                    return 0;
+                }
+                self.transport.cycle_cost(&rpc::Request::Batch(missing), options)
+            }
+        }
+    }
+}
+
+impl<T: Charging> Charging for Cache<T> {}
+
+fn observe(state: &Mutex<State>, call: &rpc::Call, result: &Result<rpc::Value>) {
+    if let Ok(value) = result {
+        let (method, params) = helpers::method_and_params(call);
+        state.lock().observe(&method, &params, value);
+    }
+}
+
+impl<T> Transport for Cache<T>
+where
+    T: Transport,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.transport.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call, options: CallOptions) -> Self::Out {
+        if let Some(value) = self.lookup(&request) {
+            return future::ok(value).boxed();
+        }
+        let state = self.state.clone();
+        self.transport
+            .send(id, request.clone(), options)
+            .map(move |result| {
+                observe(&state, &request, &result);
+                result
+            })
+            .boxed()
+    }
+
+    fn set_max_response_bytes(&mut self, v: u64) {
+        self.transport.set_max_response_bytes(v);
+    }
+}
+
+impl<T> BatchTransport for Cache<T>
+where
+    T: BatchTransport,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, Result<Vec<Result<rpc::Value>>>>;
+
+    fn send_batch<I>(&self, requests: I, options: CallOptions) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let mut cached = Vec::new();
+        let mut missing = Vec::new();
+        for (id, call) in requests {
+            match self.lookup(&call) {
+                Some(value) => cached.push(Some(value)),
+                None => {
+                    cached.push(None);
+                    missing.push((id, call));
+                }
+            }
+        }
+        if missing.is_empty() {
+            return future::ok(cached.into_iter().flatten().map(Ok).collect()).boxed();
+        }
+        let calls: Vec<_> = missing.iter().map(|(_, call)| call.clone()).collect();
+        let state = self.state.clone();
+        self.transport
+            .send_batch(missing, options)
+            .map(move |results| {
+                let results = results?;
+                if results.len() != calls.len() {
+                    return Err(Error::InvalidResponse("unexpected number of responses".into()));
+                }
+                for (call, result) in calls.iter().zip(&results) {
+                    observe(&state, call, result);
+                }
+                let mut results = results.into_iter();
+                Ok(cached
+                    .into_iter()
+                    .map(|value| match value {
+                        Some(value) => Ok(value),
+                        None => results.next().expect("one result per missing call; qed"),
+                    })
+                    .collect())
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        transports::Replay,
+        types::{Address, H256},
+        Web3,
+    };
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    fn node() -> Replay {
+        Replay::from_json(
+            r#"[
+                {"method": "eth_chainId", "params": [], "response": {"result": "0x1"}},
+                {"method": "eth_blockNumber", "params": [], "response": {"result": "0x64"}},
+                {"method": "eth_getCode", "params": ["0x0000000000000000000000000000000000000001", "latest"], "response": {"result": "0x"}},
+                {"method": "eth_getTransactionReceipt", "params": ["0x0000000000000000000000000000000000000000000000000000000000000001"], "response": {"result": {"blockNumber": "0x10"}}},
+                {"method": "eth_getTransactionReceipt", "params": ["0x0000000000000000000000000000000000000000000000000000000000000002"], "response": {"result": {"blockNumber": "0x50"}}}
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    fn execute<T: Transport>(transport: &T, method: &str, params: Vec<rpc::Value>) -> Result<rpc::Value> {
+        futures::executor::block_on(transport.execute(method, params, CallOptions::default()))
+    }
+
+    #[test]
+    fn should_answer_immutable_calls_from_the_cache() {
+        let node = node();
+        let cache = Cache::new(node.clone());
+        let web3 = Web3::new(cache.clone());
+
+        futures::executor::block_on(web3.eth().chain_id(CallOptions::default())).unwrap();
+        // The replay transport would panic on a second, unrecorded call.
+        let chain_id = futures::executor::block_on(web3.eth().chain_id(CallOptions::default()));
+
+        assert_eq!(chain_id, Ok(1.into()));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn should_only_cache_receipts_of_finalized_blocks() {
+        let cache = Cache::new(node());
+        let final_receipt = vec![serde_json::to_value(H256::from_low_u64_be(1)).unwrap()];
+        let recent_receipt = vec![serde_json::to_value(H256::from_low_u64_be(2)).unwrap()];
+
+        execute(&cache, "eth_blockNumber", vec![]).unwrap();
+        execute(&cache, "eth_getTransactionReceipt", final_receipt.clone()).unwrap();
+        execute(&cache, "eth_getTransactionReceipt", recent_receipt.clone()).unwrap();
+
+        let state = cache.state.lock();
+        assert_eq!(state.finalized, Some(100 - DEFAULT_FINALITY_DEPTH));
+        assert!(state
+            .lru
+            .entries
+            .contains_key(&cache_key("eth_getTransactionReceipt", &final_receipt.into())));
+        assert!(!state
+            .lru
+            .entries
+            .contains_key(&cache_key("eth_getTransactionReceipt", &recent_receipt.into())));
+    }
+
+    #[test]
+    fn should_not_cache_mutable_results() {
+        let cache = Cache::new(node());
+        let web3 = Web3::new(cache.clone());
+
+        futures::executor::block_on(web3.eth().block_number(CallOptions::default())).unwrap();
+        futures::executor::block_on(
+            web3.eth()
+                .code(Address::from_low_u64_be(1), None, CallOptions::default()),
+        )
+        .unwrap();
+
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn should_evict_least_recently_used_results() {
+        let mut lru = Lru::new(2);
+        lru.put("a".into(), 1.into());
+        lru.put("b".into(), 2.into());
+        lru.get("a");
+        lru.put("c".into(), 3.into());
+
+        assert_eq!(lru.get("a"), Some(1.into()));
+        assert_eq!(lru.get("b"), None);
+        assert_eq!(lru.get("c"), Some(3.into()));
+    }
+
+    #[test]
+    fn should_answer_batches_partially_from_the_cache() {
+        let cache = Cache::new(node());
+        execute(&cache, "eth_chainId", vec![]).unwrap();
+        let requests = vec![
+            cache.prepare("eth_chainId", vec![]),
+            cache.prepare("eth_blockNumber", vec![]),
+        ];
+
+        let results = futures::executor::block_on(cache.send_batch(requests, CallOptions::default()));
+
+        assert_eq!(results, Ok(vec![Ok("0x1".into()), Ok("0x64".into())]));
+    }
+
+    #[test]
+    fn should_survive_upgrades_in_stable_memory() {
+        let cache = Cache::new(node());
+        execute(&cache, "eth_chainId", vec![]).unwrap();
+        let mut map = StableBTreeMap::init(DefaultMemoryImpl::default());
+
+        cache.persist(&mut map);
+        let upgraded = Cache::new(Replay::default());
+        upgraded.restore(&map);
+
+        assert_eq!(execute(&upgraded, "eth_chainId", vec![]), Ok("0x1".into()));
+    }
+
+    #[test]
+    fn should_keep_evicting_after_restoring_into_a_used_cache() {
+        let cache = Cache::with_capacity(node(), 2);
+        let mut map = StableBTreeMap::init(DefaultMemoryImpl::default());
+        map.insert(
+            "a".to_string(),
+            CachedEntry {
+                last_used: 1,
+                value: 1.into(),
+            },
+        );
+        map.insert(
+            "b".to_string(),
+            CachedEntry {
+                last_used: 2,
+                value: 2.into(),
+            },
+        );
+        cache.state.lock().lru.put("c".into(), 3.into());
+
+        cache.restore(&map);
+        let mut state = cache.state.lock();
+        state.lru.put("d".into(), 4.into());
+
+        assert_eq!(state.lru.entries.len(), 2);
+        assert_eq!(state.lru.order.len(), 2);
+        assert_eq!(state.lru.get("b"), Some(2.into()));
+        assert_eq!(state.lru.get("d"), Some(4.into()));
+    }
+}
@@ -65,13 +65,13 @@ where
 {
     type Batch = BoxFuture<'static, error::Result<Vec<error::Result<rpc::Value>>>>;
 
-    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    fn send_batch<T>(&self, requests: T, options: CallOptions) -> Self::Batch
     where
         T: IntoIterator<Item = (RequestId, rpc::Call)>,
     {
         match *self {
-            Self::Left(ref a) => a.send_batch(requests).boxed(),
-            Self::Right(ref b) => b.send_batch(requests).boxed(),
+            Self::Left(ref a) => a.send_batch(requests, options).boxed(),
+            Self::Right(ref b) => b.send_batch(requests, options).boxed(),
         }
     }
 }
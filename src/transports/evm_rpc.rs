@@ -0,0 +1,391 @@
+//! EVM RPC canister Transport
+
+use crate::{
+    error::{Error, Result, TransportError},
+    helpers,
+    metering::{Charging, CycleCost},
+    rpc, RequestId, Transport,
+};
+use candid::{CandidType, Deserialize, Nat, Principal};
+use futures::future::{self, BoxFuture, FutureExt};
+use ic_cdk::api::call::{CallResult, RejectionCode};
+use jsonrpc_core::types::{Output, Request};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use super::ic_http_client::{CallOptions, Outcall};
+
+/// Principal of the EVM RPC canister deployed on the IC mainnet.
+pub const EVM_RPC_CANISTER_ID: &str = "7hfb6-caaaa-aaaar-qadga-cai";
+
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 500_000;
+const DEFAULT_CYCLES: u128 = 10_000_000_000;
+
+/// An HTTP header sent to a custom RPC provider.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HttpHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// A custom JSON-RPC endpoint.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RpcApi {
+    pub url: String,
+    pub headers: Option<Vec<HttpHeader>>,
+}
+
+/// Ethereum mainnet providers known to the EVM RPC canister.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EthMainnetService {
+    Alchemy,
+    Ankr,
+    BlockPi,
+    Cloudflare,
+    Llama,
+    PublicNode,
+}
+
+/// Ethereum Sepolia providers known to the EVM RPC canister.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EthSepoliaService {
+    Alchemy,
+    Ankr,
+    BlockPi,
+    PublicNode,
+    Sepolia,
+}
+
+/// Provider the EVM RPC canister forwards a request to.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum RpcService {
+    Provider(u64),
+    Custom(RpcApi),
+    EthMainnet(EthMainnetService),
+    EthSepolia(EthSepoliaService),
+}
+
+/// JSON-RPC error returned by the provider.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ProviderError {
+    TooFewCycles { expected: Nat, received: Nat },
+    MissingRequiredProvider,
+    ProviderNotFound,
+    NoPermission,
+    InvalidRpcConfig(String),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum HttpOutcallError {
+    IcError {
+        code: RejectionCode,
+        message: String,
+    },
+    InvalidHttpJsonRpcResponse {
+        status: u16,
+        body: String,
+        #[serde(rename = "parsingError")]
+        parsing_error: Option<String>,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    Custom(String),
+    InvalidHex(String),
+}
+
+/// Error variants of the EVM RPC canister.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum RpcError {
+    ProviderError(ProviderError),
+    HttpOutcallError(HttpOutcallError),
+    JsonRpcError(JsonRpcError),
+    ValidationError(ValidationError),
+}
+
+/// Result of the `request` method of the EVM RPC canister.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum RequestResult {
+    Ok(String),
+    Err(RpcError),
+}
+
+impl From<RpcError> for Error {
+    fn from(err: RpcError) -> Self {
+        match err {
+            RpcError::JsonRpcError(JsonRpcError { code, message }) => Error::Rpc(rpc::Error {
+                code: rpc::ErrorCode::from(code),
+                message,
+                data: None,
+            }),
+            RpcError::HttpOutcallError(HttpOutcallError::IcError { code, message }) => {
+                Error::Transport(TransportError::Rejected(code, message))
+            }
+            RpcError::HttpOutcallError(HttpOutcallError::InvalidHttpJsonRpcResponse { status, .. })
+                if status != 200 =>
+            {
+                Error::Transport(TransportError::Code(status))
+            }
+            RpcError::HttpOutcallError(HttpOutcallError::InvalidHttpJsonRpcResponse {
+                body, parsing_error, ..
+            }) => Error::InvalidResponse(format!("{}: {}", parsing_error.unwrap_or_default(), body)),
+            RpcError::ProviderError(err) => {
+                Error::Transport(TransportError::Message(format!("EVM RPC provider error: {:?}", err)))
+            }
+            RpcError::ValidationError(err) => {
+                Error::Transport(TransportError::Message(format!("EVM RPC validation error: {:?}", err)))
+            }
+        }
+    }
+}
+
+/// Interface of the EVM RPC canister used by [`EvmRpc`].
+///
+/// Implemented by [`EvmRpcCanister`] with inter-canister calls, tests can provide a stand-in.
+pub trait EvmRpcApi: std::fmt::Debug + Clone {
+    /// Forwards a raw JSON-RPC `request` to `service`, attaching `cycles`.
+    fn request(
+        &self,
+        service: RpcService,
+        json: String,
+        max_response_bytes: u64,
+        cycles: u128,
+    ) -> BoxFuture<'static, CallResult<(RequestResult,)>>;
+}
+
+/// The EVM RPC canister, called with inter-canister calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvmRpcCanister {
+    canister_id: Principal,
+}
+
+impl EvmRpcCanister {
+    /// EVM RPC canister with the given principal.
+    pub fn new(canister_id: Principal) -> Self {
+        EvmRpcCanister { canister_id }
+    }
+
+    /// EVM RPC canister deployed on the IC mainnet.
+    pub fn mainnet() -> Self {
+        Self::new(Principal::from_text(EVM_RPC_CANISTER_ID).expect("valid principal; qed"))
+    }
+}
+
+impl EvmRpcApi for EvmRpcCanister {
+    fn request(
+        &self,
+        service: RpcService,
+        json: String,
+        max_response_bytes: u64,
+        cycles: u128,
+    ) -> BoxFuture<'static, CallResult<(RequestResult,)>> {
+        ic_cdk::api::call::call_with_payment128(
+            self.canister_id,
+            "request",
+            (service, json, max_response_bytes),
+            cycles,
+        )
+        .boxed()
+    }
+}
+
+/// Transport routing JSON-RPC requests through the EVM RPC canister instead of HTTPS outcalls.
+///
+/// `CallOptions` `max_resp` and `cycles` override the transport defaults per call, the transform
+/// is applied by the EVM RPC canister itself.
+#[derive(Debug, Clone)]
+pub struct EvmRpc<C = EvmRpcCanister> {
+    canister: C,
+    service: RpcService,
+    max_response_bytes: u64,
+    cycles: u128,
+    id: Arc<AtomicUsize>,
+}
+
+impl<C: EvmRpcApi> EvmRpc<C> {
+    /// Creates new transport forwarding requests to `service` through `canister`.
+    pub fn new(canister: C, service: RpcService) -> Self {
+        EvmRpc {
+            canister,
+            service,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            cycles: DEFAULT_CYCLES,
+            id: Default::default(),
+        }
+    }
+
+    /// Cycles attached to each request unless `CallOptions` say otherwise.
+    ///
+    /// The EVM RPC canister refunds what it doesn't spend.
+    pub fn with_cycles(self, cycles: u128) -> Self {
+        Self { cycles, ..self }
+    }
+
+    fn next_id(&self) -> RequestId {
+        self.id.fetch_add(1, Ordering::AcqRel)
+    }
+}
+
+// Cycles the EVM RPC canister doesn't spend are refunded, so this is an upper bound.
+impl<C: EvmRpcApi> CycleCost for EvmRpc<C> {
+    fn cycle_cost(&self, _request: &Request, options: &CallOptions) -> u128 {
+        options.cycles().map(u128::from).unwrap_or(self.cycles)
+    }
+}
+
+impl<C: EvmRpcApi> Charging for EvmRpc<C> {}
+
+impl<C> Transport for EvmRpc<C>
+where
+    C: EvmRpcApi,
+{
+    type Out = BoxFuture<'static, Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.next_id();
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call, options: CallOptions) -> Self::Out {
+        let json = helpers::to_string(&Request::Single(request));
+        let max_response_bytes = options.max_resp().unwrap_or(self.max_response_bytes);
+        let cycles = options.cycles().map(u128::from).unwrap_or(self.cycles);
+        if let Some(Err(err)) = options.charger().map(|charger| charger.charge(cycles)) {
+            return future::err(err).boxed();
+        }
+        let response = self
+            .canister
+            .request(self.service.clone(), json, max_response_bytes, cycles);
+        Box::pin(async move {
+            let response = response.await;
+            // Cycles the EVM RPC canister refunds are unknown here, so the attached amount is recorded.
+            options.record_outcall(Outcall {
+                cycles,
+                response_bytes: match &response {
+                    Ok((RequestResult::Ok(body),)) => Some(body.len() as u64),
+                    _ => None,
+                },
+            });
+            let (result,) =
+                response.map_err(|(code, message)| Error::Transport(TransportError::Rejected(code, message)))?;
+            match result {
+                RequestResult::Ok(body) => {
+                    let output: Output = helpers::arbitrary_precision_deserialize_workaround(body.as_bytes())
+                        .map_err(|err| Error::InvalidResponse(format!("{}: {}", err, body)))?;
+                    helpers::to_result_from_output(output)
+                }
+                RequestResult::Err(err) => Err(err.into()),
+            }
+        })
+    }
+
+    fn set_max_response_bytes(&mut self, v: u64) {
+        self.max_response_bytes = v;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{types::U64, Web3};
+    use parking_lot::Mutex;
+
+    #[derive(Debug, Clone, Default)]
+    struct MockEvmRpc {
+        requests: Arc<Mutex<Vec<(RpcService, String, u64, u128)>>>,
+        responses: Arc<Mutex<Vec<CallResult<(RequestResult,)>>>>,
+    }
+
+    impl MockEvmRpc {
+        fn respond(&self, response: CallResult<(RequestResult,)>) {
+            self.responses.lock().push(response);
+        }
+    }
+
+    impl EvmRpcApi for MockEvmRpc {
+        fn request(
+            &self,
+            service: RpcService,
+            json: String,
+            max_response_bytes: u64,
+            cycles: u128,
+        ) -> BoxFuture<'static, CallResult<(RequestResult,)>> {
+            self.requests.lock().push((service, json, max_response_bytes, cycles));
+            futures::future::ready(self.responses.lock().remove(0)).boxed()
+        }
+    }
+
+    #[test]
+    fn should_forward_requests_to_the_canister() {
+        let canister = MockEvmRpc::default();
+        canister.respond(Ok((RequestResult::Ok(
+            r#"{"jsonrpc":"2.0","id":0,"result":"0x10"}"#.to_string(),
+        ),)));
+        let web3 = Web3::new(EvmRpc::new(
+            canister.clone(),
+            RpcService::EthMainnet(EthMainnetService::Cloudflare),
+        ));
+
+        let block_number = futures::executor::block_on(web3.eth().block_number(CallOptions::default()));
+
+        assert_eq!(block_number, Ok(U64::from(16)));
+        assert_eq!(
+            canister.requests.lock()[0],
+            (
+                RpcService::EthMainnet(EthMainnetService::Cloudflare),
+                r#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":0}"#.to_string(),
+                DEFAULT_MAX_RESPONSE_BYTES,
+                DEFAULT_CYCLES
+            )
+        );
+    }
+
+    #[test]
+    fn should_map_canister_errors() {
+        let canister = MockEvmRpc::default();
+        canister.respond(Ok((RequestResult::Err(RpcError::JsonRpcError(JsonRpcError {
+            code: -32000,
+            message: "execution reverted".to_string(),
+        })),)));
+        canister.respond(Ok((RequestResult::Err(RpcError::HttpOutcallError(
+            HttpOutcallError::IcError {
+                code: RejectionCode::SysTransient,
+                message: "timeout".to_string(),
+            },
+        )),)));
+        canister.respond(Err((RejectionCode::CanisterReject, "out of cycles".to_string())));
+        let transport = EvmRpc::new(canister, RpcService::Provider(1));
+
+        let results = (0..3)
+            .map(|_| futures::executor::block_on(transport.execute("eth_blockNumber", vec![], CallOptions::default())))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            results,
+            vec![
+                Err(Error::Rpc(rpc::Error {
+                    code: rpc::ErrorCode::ServerError(-32000),
+                    message: "execution reverted".to_string(),
+                    data: None,
+                })),
+                Err(Error::Transport(TransportError::Rejected(
+                    RejectionCode::SysTransient,
+                    "timeout".to_string()
+                ))),
+                Err(Error::Transport(TransportError::Rejected(
+                    RejectionCode::CanisterReject,
+                    "out of cycles".to_string()
+                ))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,401 @@
+//! Failover Transport
+
+use crate::{
+    error::{Error, Result},
+    helpers,
+    metering::{Charging, CycleCost},
+    rpc, BatchTransport, RequestId, Transport,
+};
+use candid::CandidType;
+use futures::future::BoxFuture;
+use jsonrpc_core::ErrorCode;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use super::{ic_http_client::CallOptions, ICHttp};
+
+type RpcResult = Result<rpc::Value>;
+
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+// Weight of the newest sample in the latency moving average, in percent.
+const LATENCY_WEIGHT: u64 = 20;
+
+thread_local! {
+    static HEALTH: RefCell<HashMap<String, EndpointHealth>> = RefCell::new(HashMap::new());
+}
+
+/// Health and latency of a single RPC endpoint.
+///
+/// Scores are kept in canister state and shared by every [`Failover`] transport using the same URL.
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct EndpointHealth {
+    /// Number of calls the endpoint answered.
+    pub successes: u64,
+    /// Number of calls that made the transport fail over.
+    pub failures: u64,
+    /// Failures since the last answered call.
+    pub consecutive_failures: u32,
+    /// Moving average of the call latency in nanoseconds.
+    pub latency_nanos: u64,
+    /// Time until which the endpoint is demoted, in nanoseconds since the UNIX epoch.
+    pub demoted_until: Option<u64>,
+}
+
+impl EndpointHealth {
+    /// Whether the endpoint is still serving its cooldown at the given time.
+    pub fn is_demoted(&self, now: u64) -> bool {
+        self.demoted_until.is_some_and(|until| until > now)
+    }
+
+    fn record_success(&mut self, latency: u64) {
+        self.successes += 1;
+        self.consecutive_failures = 0;
+        self.demoted_until = None;
+        self.latency_nanos = if self.successes == 1 {
+            latency
+        } else {
+            (self.latency_nanos * (100 - LATENCY_WEIGHT) + latency * LATENCY_WEIGHT) / 100
+        };
+    }
+
+    fn record_failure(&mut self, now: u64, cooldown: Duration, failure_threshold: u32) {
+        self.failures += 1;
+        self.consecutive_failures += 1;
+        if self.consecutive_failures >= failure_threshold {
+            self.demoted_until = Some(now.saturating_add(cooldown.as_nanos() as u64));
+        }
+    }
+}
+
+/// Returns the recorded health of an endpoint.
+pub fn endpoint_health(url: &str) -> Option<EndpointHealth> {
+    HEALTH.with(|health| health.borrow().get(url).cloned())
+}
+
+/// Returns the recorded health of every endpoint, e.g. to persist it across upgrades.
+pub fn health_snapshot() -> Vec<(String, EndpointHealth)> {
+    HEALTH.with(|health| {
+        health
+            .borrow()
+            .iter()
+            .map(|(url, health)| (url.clone(), health.clone()))
+            .collect()
+    })
+}
+
+/// Replaces the recorded health of every endpoint.
+pub fn restore_health(entries: Vec<(String, EndpointHealth)>) {
+    HEALTH.with(|health| *health.borrow_mut() = entries.into_iter().collect());
+}
+
+fn update_health<F: FnOnce(&mut EndpointHealth)>(url: &str, f: F) {
+    HEALTH.with(|health| f(health.borrow_mut().entry(url.to_string()).or_default()));
+}
+
+/// Whether an error means the endpoint is unhealthy and the call should be retried elsewhere.
+///
+/// IC rejections, non-200 HTTP statuses, unparsable responses and JSON-RPC server errors fail
+/// over. Other JSON-RPC errors are the node's answer to the request and are returned as is.
+pub fn should_fail_over(err: &Error) -> bool {
+    match err {
+        Error::Unreachable | Error::Transport(_) | Error::InvalidResponse(_) => true,
+        Error::Rpc(err) => matches!(err.code, ErrorCode::ServerError(_) | ErrorCode::InternalError),
+        _ => false,
+    }
+}
+
+/// Transport trying an ordered list of endpoints, failing over to the next one on errors.
+///
+/// Endpoints that fail `failure_threshold` times in a row are demoted for `cooldown` and only
+/// tried after every healthy endpoint.
+#[derive(Debug, Clone)]
+pub struct Failover<T> {
+    endpoints: Arc<Vec<(String, T)>>,
+    cooldown: Duration,
+    failure_threshold: u32,
+    id: Arc<AtomicUsize>,
+}
+
+impl<T: Transport> Failover<T> {
+    /// Creates new failover transport given endpoints labelled by their URL, in order of preference.
+    pub fn new<I, S>(endpoints: I) -> Self
+    where
+        I: IntoIterator<Item = (S, T)>,
+        S: Into<String>,
+    {
+        Failover {
+            endpoints: Arc::new(
+                endpoints
+                    .into_iter()
+                    .map(|(url, transport)| (url.into(), transport))
+                    .collect(),
+            ),
+            cooldown: DEFAULT_COOLDOWN,
+            failure_threshold: 1,
+            id: Default::default(),
+        }
+    }
+
+    /// Sets how long a failing endpoint stays demoted.
+    pub fn with_cooldown(self, cooldown: Duration) -> Self {
+        Self { cooldown, ..self }
+    }
+
+    /// Sets how many consecutive failures demote an endpoint.
+    pub fn with_failure_threshold(self, failure_threshold: u32) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            ..self
+        }
+    }
+
+    fn next_id(&self) -> RequestId {
+        self.id.fetch_add(1, Ordering::AcqRel)
+    }
+}
+
+impl Failover<ICHttp> {
+    /// Creates new failover transport with one `ICHttp` endpoint per URL, in order of preference.
+    pub fn from_urls(urls: &[&str], max_resp: Option<u64>) -> Result<Self> {
+        let endpoints = urls
+            .iter()
+            .map(|url| Ok((url.to_string(), ICHttp::new(url, max_resp)?)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(endpoints))
+    }
+}
+
+/// Order in which endpoints are tried: healthy ones as configured, then demoted ones by the end of their cooldown.
+fn ranked<T>(endpoints: &[(String, T)]) -> Vec<usize> {
+    let now = helpers::now_nanos();
+    let mut demoted = vec![];
+    let mut ranked = vec![];
+    for (idx, (url, _)) in endpoints.iter().enumerate() {
+        match endpoint_health(url).filter(|health| health.is_demoted(now)) {
+            Some(health) => demoted.push((health.demoted_until, idx)),
+            None => ranked.push(idx),
+        }
+    }
+    demoted.sort();
+    ranked.extend(demoted.into_iter().map(|(_, idx)| idx));
+    ranked
+}
+
+/// Tries `call` against every endpoint in turn until one of them answers.
+async fn fail_over<T, F, Fut, R>(
+    endpoints: &[(String, T)],
+    cooldown: Duration,
+    failure_threshold: u32,
+    call: F,
+) -> Result<R>
+where
+    F: Fn(&T) -> Fut,
+    Fut: std::future::Future<Output = Result<R>>,
+{
+    let mut last_error = Error::Unreachable;
+    for idx in ranked(endpoints) {
+        let (url, transport) = &endpoints[idx];
+        let started = helpers::now_nanos();
+        match call(transport).await {
+            Err(err) if should_fail_over(&err) => {
+                update_health(url, |health| {
+                    health.record_failure(helpers::now_nanos(), cooldown, failure_threshold)
+                });
+                last_error = err;
+            }
+            result => {
+                update_health(url, |health| {
+                    health.record_success(helpers::now_nanos().saturating_sub(started))
+                });
+                return result;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+// Calls go to the healthiest endpoint first and only cost more when they fail over.
+impl<T: CycleCost> CycleCost for Failover<T> {
+    fn cycle_cost(&self, request: &rpc::Request, options: &CallOptions) -> u128 {
+        ranked(&self.endpoints)
+            .first()
+            .map_or(0, |&idx| self.endpoints[idx].1.cycle_cost(request, options))
+    }
+}
+
+impl<T: Charging> Charging for Failover<T> {}
+
+impl<T> Transport for Failover<T>
+where
+    T: Transport + Send + Sync + 'static,
+    T::Out: Send,
+{
+    type Out = BoxFuture<'static, RpcResult>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.next_id();
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call, options: CallOptions) -> Self::Out {
+        let endpoints = self.endpoints.clone();
+        let (cooldown, failure_threshold) = (self.cooldown, self.failure_threshold);
+        Box::pin(async move {
+            fail_over(&endpoints, cooldown, failure_threshold, |transport| {
+                transport.send(id, request.clone(), options.clone())
+            })
+            .await
+        })
+    }
+
+    fn set_max_response_bytes(&mut self, v: u64) {
+        for (_, transport) in Arc::make_mut(&mut self.endpoints).iter_mut() {
+            transport.set_max_response_bytes(v);
+        }
+    }
+}
+
+impl<T> BatchTransport for Failover<T>
+where
+    T: BatchTransport + Send + Sync + 'static,
+    T::Out: Send,
+    T::Batch: Send,
+{
+    type Batch = BoxFuture<'static, Result<Vec<RpcResult>>>;
+
+    fn send_batch<I>(&self, requests: I, options: CallOptions) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let requests: Vec<_> = requests.into_iter().collect();
+        let endpoints = self.endpoints.clone();
+        let (cooldown, failure_threshold) = (self.cooldown, self.failure_threshold);
+        Box::pin(async move {
+            fail_over(&endpoints, cooldown, failure_threshold, |transport| {
+                transport.send_batch(requests.clone(), options.clone())
+            })
+            .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::FutureExt;
+    use parking_lot::Mutex;
+    use std::collections::VecDeque;
+
+    #[derive(Debug, Clone, Default)]
+    struct Scripted {
+        responses: Arc<Mutex<VecDeque<RpcResult>>>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Scripted {
+        fn new(responses: Vec<RpcResult>) -> Self {
+            Scripted {
+                responses: Arc::new(Mutex::new(responses.into())),
+                calls: Default::default(),
+            }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Transport for Scripted {
+        type Out = BoxFuture<'static, RpcResult>;
+
+        fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+            (0, helpers::build_request(0, method, params))
+        }
+
+        fn send(&self, _id: RequestId, _request: rpc::Call, _options: CallOptions) -> Self::Out {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let response = self.responses.lock().pop_front().unwrap_or(Err(Error::Unreachable));
+            futures::future::ready(response).boxed()
+        }
+    }
+
+    fn rpc_error(code: ErrorCode) -> RpcResult {
+        Err(Error::Rpc(rpc::Error::new(code)))
+    }
+
+    #[test]
+    fn should_fail_over_on_server_error() {
+        let primary = Scripted::new(vec![rpc_error(ErrorCode::ServerError(-32005))]);
+        let secondary = Scripted::new(vec![Ok("0x1".into())]);
+        let transport = Failover::new(vec![
+            ("primary-server-error", primary),
+            ("secondary-server-error", secondary),
+        ]);
+
+        let result = futures::executor::block_on(transport.execute("eth_blockNumber", vec![], CallOptions::default()));
+
+        assert_eq!(result, Ok("0x1".into()));
+        let health = endpoint_health("primary-server-error").unwrap();
+        assert_eq!(health.failures, 1);
+        assert!(health.is_demoted(helpers::now_nanos()));
+        assert_eq!(endpoint_health("secondary-server-error").unwrap().successes, 1);
+    }
+
+    #[test]
+    fn should_return_non_server_errors() {
+        let primary = Scripted::new(vec![rpc_error(ErrorCode::InvalidParams)]);
+        let secondary = Scripted::new(vec![Ok("0x1".into())]);
+        let transport = Failover::new(vec![
+            ("primary-invalid-params", primary),
+            ("secondary-invalid-params", secondary.clone()),
+        ]);
+
+        let result = futures::executor::block_on(transport.execute("eth_blockNumber", vec![], CallOptions::default()));
+
+        assert_eq!(result, rpc_error(ErrorCode::InvalidParams));
+        assert_eq!(secondary.calls(), 0);
+    }
+
+    #[test]
+    fn should_try_demoted_endpoints_last() {
+        let primary = Scripted::new(vec![Err(Error::Unreachable)]);
+        let secondary = Scripted::new(vec![Ok("0x1".into()), Ok("0x2".into())]);
+        let transport = Failover::new(vec![
+            ("primary-demoted", primary.clone()),
+            ("secondary-demoted", secondary),
+        ]);
+
+        let first = futures::executor::block_on(transport.execute("eth_blockNumber", vec![], CallOptions::default()));
+        let second = futures::executor::block_on(transport.execute("eth_blockNumber", vec![], CallOptions::default()));
+
+        assert_eq!(first, Ok("0x1".into()));
+        assert_eq!(second, Ok("0x2".into()));
+        assert_eq!(primary.calls(), 1);
+    }
+
+    #[test]
+    fn should_only_demote_after_threshold() {
+        let primary = Scripted::new(vec![Err(Error::Unreachable), Ok("0x2".into())]);
+        let secondary = Scripted::new(vec![Ok("0x1".into())]);
+        let transport = Failover::new(vec![
+            ("primary-threshold", primary.clone()),
+            ("secondary-threshold", secondary),
+        ])
+        .with_failure_threshold(2);
+
+        futures::executor::block_on(transport.execute("eth_blockNumber", vec![], CallOptions::default())).unwrap();
+        let second = futures::executor::block_on(transport.execute("eth_blockNumber", vec![], CallOptions::default()));
+
+        assert_eq!(second, Ok("0x2".into()));
+        assert_eq!(primary.calls(), 2);
+        assert_eq!(endpoint_health("primary-threshold").unwrap().consecutive_failures, 0);
+    }
+}
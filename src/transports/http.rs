@@ -4,6 +4,8 @@ use crate::{
     error::{Error, Result, TransportError},
     helpers, BatchTransport, RequestId, Transport,
 };
+
+use super::ic_http_client::CallOptions;
 #[cfg(not(feature = "wasm"))]
 use futures::future::BoxFuture;
 #[cfg(feature = "wasm")]
@@ -11,12 +13,9 @@ use futures::future::LocalBoxFuture as BoxFuture;
 use jsonrpc_core::types::{Call, Output, Request, Value};
 use reqwest::{Client, Url};
 use serde::de::DeserializeOwned;
-use std::{
-    collections::HashMap,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
-    },
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
 };
 
 /// HTTP Transport
@@ -96,13 +95,7 @@ async fn execute_rpc<T: DeserializeOwned>(client: &Client, url: Url, request: &R
     if !status.is_success() {
         return Err(Error::Transport(TransportError::Code(status.as_u16())));
     }
-    helpers::arbitrary_precision_deserialize_workaround(&response).map_err(|err| {
-        Error::Transport(TransportError::Message(format!(
-            "failed to deserialize response: {}: {}",
-            err,
-            String::from_utf8_lossy(&response)
-        )))
-    })
+    helpers::to_response_from_body(&response)
 }
 
 type RpcResult = Result<Value>;
@@ -116,7 +109,8 @@ impl Transport for Http {
         (id, request)
     }
 
-    fn send(&self, id: RequestId, call: Call) -> Self::Out {
+    /// Sends `call` straight to the node. `options` only matter to outcalls and are ignored.
+    fn send(&self, id: RequestId, call: Call, _options: CallOptions) -> Self::Out {
         let (client, url) = self.new_request();
         Box::pin(async move {
             let output: Output = execute_rpc(&client, url, &Request::Single(call), id).await?;
@@ -128,7 +122,7 @@ impl Transport for Http {
 impl BatchTransport for Http {
     type Batch = BoxFuture<'static, Result<Vec<RpcResult>>>;
 
-    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    fn send_batch<T>(&self, requests: T, _options: CallOptions) -> Self::Batch
     where
         T: IntoIterator<Item = (RequestId, Call)>,
     {
@@ -137,108 +131,81 @@ impl BatchTransport for Http {
         let (client, url) = self.new_request();
         let (ids, calls): (Vec<_>, Vec<_>) = requests.into_iter().unzip();
         Box::pin(async move {
-            let outputs: Vec<Output> = execute_rpc(&client, url, &Request::Batch(calls), id).await?;
-            handle_batch_response(&ids, outputs)
+            let response = execute_rpc(&client, url, &Request::Batch(calls), id).await?;
+            helpers::to_results_from_batch(&ids, helpers::to_outputs_from_batch_response(response)?)
         })
     }
 }
 
-// According to the jsonrpc specification batch responses can be returned in any order so we need to
-// restore the intended order.
-fn handle_batch_response(ids: &[RequestId], outputs: Vec<Output>) -> Result<Vec<RpcResult>> {
-    if ids.len() != outputs.len() {
-        return Err(Error::InvalidResponse("unexpected number of responses".to_string()));
-    }
-    let mut outputs = outputs
-        .into_iter()
-        .map(|output| Ok((id_of_output(&output)?, helpers::to_result_from_output(output))))
-        .collect::<Result<HashMap<_, _>>>()?;
-    ids.iter()
-        .map(|id| {
-            outputs
-                .remove(id)
-                .ok_or_else(|| Error::InvalidResponse(format!("batch response is missing id {}", id)))
-        })
-        .collect()
-}
-
-fn id_of_output(output: &Output) -> Result<RequestId> {
-    let id = match output {
-        Output::Success(success) => &success.id,
-        Output::Failure(failure) => &failure.id,
-    };
-    match id {
-        jsonrpc_core::Id::Num(num) => Ok(*num as RequestId),
-        _ => Err(Error::InvalidResponse("response id is not u64".to_string())),
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
 
+    /// Answers calls with their id as result, batches in reverse order.
     async fn server(req: hyper::Request<hyper::Body>) -> hyper::Result<hyper::Response<hyper::Body>> {
-        use hyper::body::HttpBody;
-
-        let expected = r#"{"jsonrpc":"2.0","method":"eth_getAccounts","params":[],"id":0}"#;
-        let response = r#"{"jsonrpc":"2.0","id":0,"result":"x"}"#;
-
         assert_eq!(req.method(), &hyper::Method::POST);
         assert_eq!(req.uri().path(), "/");
-        let mut content: Vec<u8> = vec![];
-        let mut body = req.into_body();
-        while let Some(Ok(chunk)) = body.data().await {
-            content.extend(&*chunk);
-        }
-        assert_eq!(std::str::from_utf8(&*content), Ok(expected));
+        let body = hyper::body::to_bytes(req.into_body()).await?;
+        let response = match serde_json::from_slice::<Request>(&body).unwrap() {
+            Request::Single(Call::MethodCall(call)) if call.method == "eth_getAccounts" => {
+                assert_eq!(
+                    std::str::from_utf8(&body),
+                    Ok(r#"{"jsonrpc":"2.0","method":"eth_getAccounts","params":[],"id":0}"#)
+                );
+                json!({"jsonrpc": "2.0", "id": call.id, "result": "x"})
+            }
+            Request::Single(Call::MethodCall(call)) => {
+                json!({"jsonrpc": "2.0", "id": call.id, "error": {"code": -32601, "message": "Method not found"}})
+            }
+            Request::Batch(calls) => calls
+                .into_iter()
+                .rev()
+                .map(|call| match call {
+                    Call::MethodCall(call) => json!({"jsonrpc": "2.0", "id": call.id, "result": call.id}),
+                    _ => unreachable!(),
+                })
+                .collect(),
+            _ => unreachable!(),
+        };
+        Ok(hyper::Response::new(response.to_string().into()))
+    }
 
-        Ok(hyper::Response::new(response.into()))
+    fn serve() -> Http {
+        use hyper::service::{make_service_fn, service_fn};
+
+        let service = make_service_fn(|_| async { Ok::<_, hyper::Error>(service_fn(server)) });
+        let server = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        Http::new(&url).unwrap()
     }
 
     #[tokio::test]
     async fn should_make_a_request() {
-        use hyper::service::{make_service_fn, service_fn};
+        let client = serve();
+
+        let response = client.execute("eth_getAccounts", vec![], CallOptions::default()).await;
 
-        // given
-        let addr = "127.0.0.1:3001";
-        // start server
-        let service = make_service_fn(|_| async { Ok::<_, hyper::Error>(service_fn(server)) });
-        let server = hyper::Server::bind(&addr.parse().unwrap()).serve(service);
-        tokio::spawn(async move {
-            println!("Listening on http://{}", addr);
-            server.await.unwrap();
-        });
-
-        // when
-        let client = Http::new(&format!("http://{}", addr)).unwrap();
-        println!("Sending request");
-        let response = client.execute("eth_getAccounts", vec![]).await;
-        println!("Got response");
-
-        // then
         assert_eq!(response, Ok(Value::String("x".into())));
     }
 
-    #[test]
-    fn handles_batch_response_being_in_different_order_than_input() {
-        let ids = vec![0, 1, 2];
-        // This order is different from the ids.
-        let outputs = [1u64, 0, 2]
-            .iter()
-            .map(|&id| {
-                Output::Success(jsonrpc_core::Success {
-                    jsonrpc: None,
-                    result: id.into(),
-                    id: jsonrpc_core::Id::Num(id),
-                })
-            })
-            .collect();
-        let results = handle_batch_response(&ids, outputs)
-            .unwrap()
-            .into_iter()
-            .map(|result| result.unwrap().as_u64().unwrap() as usize)
-            .collect::<Vec<_>>();
-        // The order of the ids should have been restored.
-        assert_eq!(ids, results);
+    #[tokio::test]
+    async fn should_return_rpc_errors() {
+        let client = serve();
+
+        let response = client.execute("eth_unknown", vec![], CallOptions::default()).await;
+
+        assert!(matches!(response, Err(Error::Rpc(err)) if err.code.code() == -32601));
+    }
+
+    #[tokio::test]
+    async fn should_restore_the_order_of_batches() {
+        let client = serve();
+        let requests: Vec<_> = (0..3).map(|_| client.prepare("eth_blockNumber", vec![])).collect();
+
+        let results = client.send_batch(requests, CallOptions::default()).await;
+
+        assert_eq!(results, Ok(vec![Ok(json!(0)), Ok(json!(1)), Ok(json!(2))]));
     }
 }
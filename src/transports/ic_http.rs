@@ -1,14 +1,17 @@
 //! IC HTTP Transport
 
-use crate::transports::ICHttpClient;
+use crate::transports::{HttpOutcallBackend, ICHttpClient, RetryPolicy};
 use crate::{
     error::{Error, Result, TransportError},
-    helpers, BatchTransport, RequestId, Transport,
+    helpers,
+    metering::{Charging, CycleCost},
+    transforms::registry::TransformSpec,
+    BatchTransport, RequestId, Transport,
 };
 #[cfg(not(feature = "wasm"))]
 use futures::future::BoxFuture;
 use ic_cdk::api::management_canister::http_request::TransformContext;
-use jsonrpc_core::types::{Call, Output, Request, Value};
+use jsonrpc_core::types::{Call, Output, Request, Response, Value};
 use serde::de::DeserializeOwned;
 use std::{
     collections::HashMap,
@@ -24,6 +27,7 @@ pub use super::ic_http_client::{CallOptions, CallOptionsBuilder};
 #[derive(Clone, Debug)]
 pub struct ICHttp {
     client: ICHttpClient,
+    retry: RetryPolicy,
     inner: Arc<Inner>,
 }
 
@@ -42,6 +46,7 @@ impl ICHttp {
     pub fn new(url: &str, max_resp: Option<u64>) -> Result<Self> {
         Ok(Self {
             client: ICHttpClient::new(max_resp),
+            retry: RetryPolicy::none(),
             inner: Arc::new(Inner {
                 url: url.to_string(),
                 id: AtomicUsize::new(0),
@@ -49,15 +54,77 @@ impl ICHttp {
         })
     }
 
+    /// Retry transient failures according to `policy`. By default every call is attempted once.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Make outcalls with `backend` instead of the management canister, e.g. a
+    /// [`NativeOutcallBackend`](crate::transports::NativeOutcallBackend) in unit tests.
+    pub fn with_backend<B: HttpOutcallBackend + 'static>(mut self, backend: B) -> Self {
+        self.client.set_backend(Arc::new(backend));
+        self
+    }
+
+    /// Compute outcall fees for a subnet of `subnet_size` nodes instead of the default 13.
+    pub fn with_subnet_size(mut self, subnet_size: u32) -> Self {
+        self.client.set_subnet_size(subnet_size);
+        self
+    }
+
+    /// Send `name: value` with every request, e.g. an `x-api-key` header of a paid RPC provider.
+    ///
+    /// Header values are never printed, so keys don't need to be embedded in the URL.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.client.set_header(name, value);
+        self
+    }
+
+    /// Authenticate every request with an `Authorization: Bearer` token.
+    pub fn with_bearer_auth(self, token: &str) -> Self {
+        self.with_header("Authorization", &format!("Bearer {}", token))
+    }
+
+    /// Authenticate every request with HTTP basic auth.
+    pub fn with_basic_auth(self, username: &str, password: &str) -> Self {
+        let credentials = base64::encode(format!("{}:{}", username, password));
+        self.with_header("Authorization", &format!("Basic {}", credentials))
+    }
+
+    /// Estimate the cycles a call to `method` would cost, so that canisters can check they can
+    /// afford it before making it.
+    pub fn estimate_cost(&self, method: &str, params: Vec<Value>, options: &CallOptions) -> u128 {
+        let id = self.inner.id.load(Ordering::Acquire);
+        let request = Request::Single(helpers::build_request(id, method, params));
+        self.cycle_cost(&request, options)
+    }
+
+    /// The URL of the RPC endpoint this transport talks to.
+    pub fn url(&self) -> &str {
+        &self.inner.url
+    }
+
     fn next_id(&self) -> RequestId {
         self.inner.id.fetch_add(1, Ordering::AcqRel)
     }
 
-    fn new_request(&self) -> (ICHttpClient, String) {
-        (self.client.clone(), self.inner.url.clone())
+    fn new_request(&self) -> (ICHttpClient, String, RetryPolicy) {
+        (self.client.clone(), self.inner.url.clone(), self.retry.clone())
     }
 }
 
+impl CycleCost for ICHttp {
+    fn cycle_cost(&self, request: &Request, options: &CallOptions) -> u128 {
+        let options = self
+            .client
+            .with_transform_spec(options.clone(), &TransformSpec::for_request(request));
+        self.client.estimate_cost(self.inner.url.clone(), request, &options)
+    }
+}
+
+impl Charging for ICHttp {}
+
 // Id is only used for logging.
 async fn execute_rpc<T: DeserializeOwned>(
     client: &ICHttpClient,
@@ -66,17 +133,9 @@ async fn execute_rpc<T: DeserializeOwned>(
     id: RequestId,
     options: CallOptions,
 ) -> Result<T> {
-    let response = client
-        .post(url, request, options)
-        .await
-        .map_err(|err| Error::Transport(TransportError::Message(err)))?;
-    helpers::arbitrary_precision_deserialize_workaround(&response).map_err(|err| {
-        Error::Transport(TransportError::Message(format!(
-            "failed to deserialize response: {}: {}",
-            err,
-            String::from_utf8_lossy(&response)
-        )))
-    })
+    client.charge(url.clone(), request, &options)?;
+    let response = client.post(url, request, options).await.map_err(Error::Transport)?;
+    helpers::to_response_from_body(&response)
 }
 
 type RpcResult = Result<Value>;
@@ -91,10 +150,16 @@ impl Transport for ICHttp {
     }
 
     fn send(&self, id: RequestId, call: Call, options: CallOptions) -> Self::Out {
-        let (client, url) = self.new_request();
+        let (client, url, retry) = self.new_request();
         Box::pin(async move {
-            let output: Output = execute_rpc(&client, url, &Request::Single(call), id, options).await?;
-            helpers::to_result_from_output(output)
+            let request = Request::Single(call);
+            let options = client.with_transform_spec(options, &TransformSpec::for_request(&request));
+            retry
+                .run(options, client.max_response_bytes, |options| async {
+                    let output: Output = execute_rpc(&client, url.clone(), &request, id, options).await?;
+                    helpers::to_result_from_output(output)
+                })
+                .await
         })
     }
 
@@ -106,55 +171,43 @@ impl Transport for ICHttp {
 impl BatchTransport for ICHttp {
     type Batch = BoxFuture<'static, Result<Vec<RpcResult>>>;
 
-    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    fn send_batch<T>(&self, requests: T, options: CallOptions) -> Self::Batch
     where
         T: IntoIterator<Item = (RequestId, Call)>,
     {
         // Batch calls don't need an id but it helps associate the response log with the request log.
         let id = self.next_id();
-        let (client, url) = self.new_request();
+        let (client, url, retry) = self.new_request();
         let (ids, calls): (Vec<_>, Vec<_>) = requests.into_iter().unzip();
         Box::pin(async move {
-            let outputs: Vec<Output> =
-                execute_rpc(&client, url, &Request::Batch(calls), id, CallOptions::default()).await?;
-            handle_batch_response(&ids, outputs)
+            let request = Request::Batch(calls);
+            let options = client.with_transform_spec(options, &TransformSpec::for_request(&request));
+            let outputs = retry
+                .run(options, client.max_response_bytes, |options| async {
+                    let response = execute_rpc(&client, url.clone(), &request, id, options).await?;
+                    helpers::to_outputs_from_batch_response(response)
+                })
+                .await?;
+            helpers::to_results_from_batch(&ids, outputs)
         })
     }
 }
 
-// According to the jsonrpc specification batch responses can be returned in any order so we need to
-// restore the intended order.
-fn handle_batch_response(ids: &[RequestId], outputs: Vec<Output>) -> Result<Vec<RpcResult>> {
-    if ids.len() != outputs.len() {
-        return Err(Error::InvalidResponse("unexpected number of responses".to_string()));
-    }
-    let mut outputs = outputs
-        .into_iter()
-        .map(|output| Ok((id_of_output(&output)?, helpers::to_result_from_output(output))))
-        .collect::<Result<HashMap<_, _>>>()?;
-    ids.iter()
-        .map(|id| {
-            outputs
-                .remove(id)
-                .ok_or_else(|| Error::InvalidResponse(format!("batch response is missing id {}", id)))
-        })
-        .collect()
-}
-
-fn id_of_output(output: &Output) -> Result<RequestId> {
-    let id = match output {
-        Output::Success(success) => &success.id,
-        Output::Failure(failure) => &failure.id,
-    };
-    match id {
-        jsonrpc_core::Id::Num(num) => Ok(*num as RequestId),
-        _ => Err(Error::InvalidResponse("response id is not u64".to_string())),
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        transforms::{
+            processors::send_transaction_processor,
+            registry::{self, TRANSFORM_METHOD},
+            transform::HTTP_ERROR_CODE,
+        },
+        transports::NativeOutcallBackend,
+    };
+    use ic_cdk::api::{
+        call::RejectionCode,
+        management_canister::http_request::{CanisterHttpRequestArgument, HttpResponse, TransformArgs, TransformFunc},
+    };
 
     async fn server(req: hyper::Request<hyper::Body>) -> hyper::Result<hyper::Response<hyper::Body>> {
         use hyper::body::HttpBody;
@@ -198,6 +251,201 @@ mod tests {
         //assert_eq!(response, Ok(Value::String("x".into())));
     }
 
+    fn ok(body: &str) -> HttpResponse {
+        HttpResponse {
+            status: 200u8.into(),
+            headers: vec![],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    /// Answers every call with its own id as result, in reverse order for batches.
+    fn echo_server(request: &CanisterHttpRequestArgument) -> HttpResponse {
+        let echo =
+            |call: &serde_json::Value| serde_json::json!({"jsonrpc": "2.0", "id": call["id"], "result": call["id"]});
+        let body: serde_json::Value = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+        let response = match body.as_array() {
+            Some(calls) => calls.iter().rev().map(echo).collect(),
+            None => echo(&body),
+        };
+        ok(&response.to_string())
+    }
+
+    fn identity(args: TransformArgs) -> HttpResponse {
+        args.response
+    }
+
+    #[test]
+    fn should_make_a_request_natively() {
+        let backend = NativeOutcallBackend::new(echo_server).with_transform("transform", identity);
+        let transport = ICHttp::new("https://rpc.example.com", None)
+            .unwrap()
+            .with_header("x-api-key", "secret")
+            .with_backend(backend.clone());
+
+        let response =
+            futures::executor::block_on(transport.execute("eth_blockNumber", vec![], CallOptions::default()));
+
+        assert_eq!(response, Ok(Value::from(0)));
+        let (request, cycles) = backend.requests().remove(0);
+        assert_eq!(request.url, "https://rpc.example.com");
+        assert_eq!(
+            request.body,
+            Some(br#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":0}"#.to_vec())
+        );
+        assert_eq!(request.max_response_bytes, Some(500_000));
+        assert!(request
+            .headers
+            .iter()
+            .any(|h| h.name == "x-api-key" && h.value == "secret"));
+        assert!(cycles > 0);
+    }
+
+    #[test]
+    fn should_apply_the_transform_of_the_call() {
+        let backend = NativeOutcallBackend::new(|_: &CanisterHttpRequestArgument| {
+            ok(r#"{"jsonrpc":"2.0","id":0,"result":{"hash":"0x1","transactionIndex":"0x5"}}"#)
+        })
+        .with_processor("transform_send_transaction", send_transaction_processor());
+        let transport = ICHttp::new("https://rpc.example.com", None)
+            .unwrap()
+            .with_backend(backend);
+        let options = CallOptionsBuilder::default()
+            .max_resp(None)
+            .cycles(None)
+            .transform(Some(TransformContext {
+                function: TransformFunc(candid::Func {
+                    principal: candid::Principal::anonymous(),
+                    method: "transform_send_transaction".to_string(),
+                }),
+                context: vec![],
+            }))
+            .build()
+            .unwrap();
+
+        let response = futures::executor::block_on(transport.execute("eth_getTransactionByHash", vec![], options));
+
+        assert_eq!(
+            response,
+            Ok(serde_json::json!({"hash": "0x1", "transactionIndex": "0x0"}))
+        );
+    }
+
+    #[test]
+    fn should_fill_the_transform_context_per_method() {
+        let backend = NativeOutcallBackend::new(|_: &CanisterHttpRequestArgument| {
+            ok(r#"{"jsonrpc":"2.0","id":0,"result":[{"logIndex":"0x05","transactionIndex":"0x3"}]}"#)
+        })
+        .with_transform(TRANSFORM_METHOD, registry::transform);
+        let transport = ICHttp::new("https://rpc.example.com", None)
+            .unwrap()
+            .with_backend(backend.clone());
+
+        let response = futures::executor::block_on(transport.execute("eth_getLogs", vec![], CallOptions::default()));
+
+        assert_eq!(
+            response,
+            Ok(serde_json::json!([{"logIndex": "0x5", "transactionIndex": "0x3"}]))
+        );
+        let (request, _) = backend.requests().remove(0);
+        assert_eq!(
+            TransformSpec::decode(&request.transform.unwrap().context),
+            Ok(TransformSpec::for_method("eth_getLogs"))
+        );
+    }
+
+    #[test]
+    fn should_reject_unregistered_transforms() {
+        let transport = ICHttp::new("https://rpc.example.com", None)
+            .unwrap()
+            .with_backend(NativeOutcallBackend::new(echo_server));
+
+        let response =
+            futures::executor::block_on(transport.execute("eth_blockNumber", vec![], CallOptions::default()));
+
+        assert!(matches!(
+            response,
+            Err(Error::Transport(TransportError::Rejected(
+                RejectionCode::CanisterError,
+                _
+            )))
+        ));
+    }
+
+    #[test]
+    fn should_surface_http_errors_as_rpc_errors() {
+        let backend = NativeOutcallBackend::new(|_: &CanisterHttpRequestArgument| HttpResponse {
+            status: 503u16.into(),
+            headers: vec![],
+            body: b"<html>Service Unavailable</html>".to_vec(),
+        })
+        .with_transform(TRANSFORM_METHOD, registry::transform);
+        let transport = ICHttp::new("https://rpc.example.com", None)
+            .unwrap()
+            .with_retry_policy(RetryPolicy::default())
+            .with_backend(backend.clone());
+
+        let single = futures::executor::block_on(transport.execute("eth_blockNumber", vec![], CallOptions::default()));
+        let batch = futures::executor::block_on(transport.send_batch(
+            vec![transport.prepare("eth_blockNumber", vec![])],
+            CallOptions::default(),
+        ));
+
+        match (single, batch) {
+            (Err(Error::Rpc(single)), Err(Error::Rpc(batch))) => {
+                assert_eq!(single.code.code(), HTTP_ERROR_CODE);
+                assert_eq!(single, batch);
+            }
+            other => panic!("unexpected responses: {:?}", other),
+        }
+        // 503 is retryable.
+        assert_eq!(
+            backend.requests().len(),
+            2 * RetryPolicy::default().max_attempts as usize
+        );
+    }
+
+    #[test]
+    fn should_grow_max_response_bytes_when_the_response_is_too_large() {
+        let large = format!(r#"{{"jsonrpc":"2.0","id":0,"result":"0x{}"}}"#, "0".repeat(1_500));
+        let backend = NativeOutcallBackend::new(move |_: &CanisterHttpRequestArgument| ok(&large))
+            .with_transform("transform", identity);
+        let transport = ICHttp::new("https://rpc.example.com", Some(1_000))
+            .unwrap()
+            .with_retry_policy(RetryPolicy::default())
+            .with_backend(backend.clone());
+
+        let response = futures::executor::block_on(transport.execute("eth_call", vec![], CallOptions::default()));
+
+        assert!(response.is_ok());
+        assert_eq!(
+            backend
+                .requests()
+                .iter()
+                .map(|(request, _)| request.max_response_bytes)
+                .collect::<Vec<_>>(),
+            vec![Some(1_000), Some(2_000)]
+        );
+    }
+
+    #[test]
+    fn should_restore_the_order_of_batch_responses() {
+        let backend = NativeOutcallBackend::new(echo_server).with_transform("transform", identity);
+        let transport = ICHttp::new("https://rpc.example.com", None)
+            .unwrap()
+            .with_backend(backend);
+        let requests = (0..3)
+            .map(|_| transport.prepare("eth_blockNumber", vec![]))
+            .collect::<Vec<_>>();
+
+        let results = futures::executor::block_on(transport.send_batch(requests, CallOptions::default())).unwrap();
+
+        assert_eq!(
+            results,
+            vec![Ok(Value::from(0)), Ok(Value::from(1)), Ok(Value::from(2))]
+        );
+    }
+
     #[test]
     fn handles_batch_response_being_in_different_order_than_input() {
         let ids = vec![0, 1, 2];
@@ -212,7 +460,7 @@ mod tests {
                 })
             })
             .collect();
-        let results = handle_batch_response(&ids, outputs)
+        let results = helpers::to_results_from_batch(&ids, outputs)
             .unwrap()
             .into_iter()
             .map(|result| result.unwrap().as_u64().unwrap() as usize)
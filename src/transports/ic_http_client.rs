@@ -1,13 +1,29 @@
 //! IC http client
 
+use crate::{
+    error::TransportError,
+    metering::Charger,
+    transforms::registry::{TransformSpec, TRANSFORM_METHOD},
+};
 use candid::CandidType;
-use candid::{candid_method, Principal};
+use candid::{candid_method, Nat, Principal};
 use derive_builder::Builder;
 use ic_cdk::api::management_canister::http_request::{
-    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformContext, TransformFunc,
+    CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformContext, TransformFunc,
 };
 use jsonrpc_core::Request;
+use parking_lot::Mutex;
 use serde::{self, Deserialize, Serialize};
+use std::{
+    convert::TryFrom,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::outcall::{HttpOutcallBackend, IcOutcallBackend};
 
 // #[derive(CandidType, Deserialize, Debug)]
 // pub struct CanisterHttpRequestArgs {
@@ -19,39 +35,326 @@ use serde::{self, Deserialize, Serialize};
 //     pub transform_method_name: Option<String>,
 // }
 
-#[derive(Clone, Debug)]
+/// Subnet size the outcall fee is computed for unless configured otherwise.
+pub const DEFAULT_SUBNET_SIZE: u32 = 13;
+
+#[derive(Clone)]
 pub struct ICHttpClient {
     pub max_response_bytes: u64,
+    /// Number of nodes of the subnet the canister runs on, the outcall fee scales with it.
+    pub subnet_size: u32,
+    /// Headers sent with every request, e.g. API keys of paid RPC providers.
+    headers: Vec<HttpHeader>,
+    /// Makes the outcalls, the management canister unless running natively.
+    backend: Arc<dyn HttpOutcallBackend>,
+}
+
+impl fmt::Debug for ICHttpClient {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ICHttpClient")
+            .field("max_response_bytes", &self.max_response_bytes)
+            .field("subnet_size", &self.subnet_size)
+            .field("headers", &header_names(&self.headers))
+            .field("backend", &self.backend)
+            .finish()
+    }
+}
+
+/// Whether every node of the subnet makes an outcall, or a single one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Replication {
+    /// Every node makes the outcall and the response goes through consensus.
+    #[default]
+    Replicated,
+    /// A single node makes the outcall, for a fraction of the cycles. Its response is trusted as is,
+    /// so it only suits low-stakes reads such as UI hints or price previews.
+    NonReplicated,
+}
+
+/// How the results of a call were obtained, shared by the clones of the `CallOptions` tracking it.
+///
+/// `ICHttp` marks it when it makes a non-replicated outcall, so that callers can tell whether a
+/// result went through consensus whatever the transports in between did to the options.
+#[derive(Debug, Clone, Default)]
+pub struct Provenance {
+    non_replicated: Arc<AtomicBool>,
+}
+
+impl Provenance {
+    /// Creates new provenance of a call which made no outcall yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an outcall made with `replication`.
+    pub fn mark(&self, replication: Replication) {
+        if replication == Replication::NonReplicated {
+            self.non_replicated.store(true, Ordering::Release);
+        }
+    }
+
+    /// Non-replicated when any outcall of the call was.
+    pub fn replication(&self) -> Replication {
+        if self.non_replicated.load(Ordering::Acquire) {
+            Replication::NonReplicated
+        } else {
+            Replication::Replicated
+        }
+    }
+}
+
+// Provenances are equal when they track the same call.
+impl PartialEq for Provenance {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.non_replicated, &other.non_replicated)
+    }
+}
+
+impl Eq for Provenance {}
+
+/// Outcall made for a call, as recorded in an [`OutcallLog`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcall {
+    /// Cycles attached to the outcall.
+    pub cycles: u128,
+    /// Size of the response body and headers as they reached the canister, `None` when the outcall
+    /// was rejected.
+    pub response_bytes: Option<u64>,
+}
+
+/// Outcalls made for a call, shared by the clones of the `CallOptions` logging them.
+///
+/// `ICHttp` and `EvmRpc` append every outcall they make, retries and failovers included, so that
+/// wrappers such as [`Metrics`](super::Metrics) see what was actually spent and received.
+#[derive(Debug, Clone, Default)]
+pub struct OutcallLog {
+    outcalls: Arc<Mutex<Vec<Outcall>>>,
+}
+
+impl OutcallLog {
+    /// Creates new log of a call which made no outcall yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an outcall.
+    pub fn record(&self, outcall: Outcall) {
+        self.outcalls.lock().push(outcall);
+    }
+
+    /// The outcalls recorded so far, in the order they were made.
+    pub fn outcalls(&self) -> Vec<Outcall> {
+        self.outcalls.lock().clone()
+    }
+}
+
+// Logs are equal when they log the same call.
+impl PartialEq for OutcallLog {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.outcalls, &other.outcalls)
+    }
 }
 
-#[derive(Builder, Default, Clone, Debug, PartialEq, Eq)]
+impl Eq for OutcallLog {}
+
+#[derive(Builder, Default, Clone, PartialEq, Eq)]
 pub struct CallOptions {
     max_resp: Option<u64>,
+    /// Cycles to attach to the outcall instead of the computed fee.
     cycles: Option<u64>,
     transform: Option<TransformContext>,
+    /// Refuse to make the outcall when it would cost more cycles than this.
+    #[builder(default)]
+    max_cycles: Option<u64>,
+    /// Headers overriding the default headers of the transport for this call.
+    #[builder(default)]
+    headers: Vec<HttpHeader>,
+    /// Outcall mode, replicated unless set otherwise.
+    #[builder(default)]
+    replication: Replication,
+    /// Charges the cycles of every outcall made for the call, set by `Metered` transports.
+    #[builder(default)]
+    charger: Option<Charger>,
+    /// Marked with the replication of every outcall made for the call.
+    #[builder(default)]
+    provenance: Vec<Provenance>,
+    /// Logs every outcall made for the call.
+    #[builder(default)]
+    outcall_logs: Vec<OutcallLog>,
+}
+
+// Header values are left out so that credentials never end up in logs.
+impl fmt::Debug for CallOptions {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("CallOptions")
+            .field("max_resp", &self.max_resp)
+            .field("cycles", &self.cycles)
+            .field("transform", &self.transform)
+            .field("max_cycles", &self.max_cycles)
+            .field("headers", &header_names(&self.headers))
+            .field("replication", &self.replication)
+            .field("charger", &self.charger.as_ref().map(Charger::caller))
+            .field("provenance", &self.provenance)
+            .field("outcall_logs", &self.outcall_logs)
+            .finish()
+    }
+}
+
+impl CallOptions {
+    /// Maximum response size of the outcall, the transport default when `None`.
+    pub fn max_resp(&self) -> Option<u64> {
+        self.max_resp
+    }
+
+    pub fn set_max_resp(&mut self, max_resp: Option<u64>) {
+        self.max_resp = max_resp;
+    }
+
+    /// Cycles attached to the call, the computed fee when `None`.
+    pub fn cycles(&self) -> Option<u64> {
+        self.cycles
+    }
+
+    /// Outcall mode of the call. Results of calls made with [`Replication::NonReplicated`] didn't go
+    /// through consensus, a tracked [`Provenance`] tells whether they were.
+    pub fn replication(&self) -> Replication {
+        self.replication
+    }
+
+    pub fn set_replication(&mut self, replication: Replication) {
+        self.replication = replication;
+    }
+
+    /// Charger the cycles of outcalls made for the call are charged with.
+    pub fn charger(&self) -> Option<&Charger> {
+        self.charger.as_ref()
+    }
+
+    pub fn set_charger(&mut self, charger: Option<Charger>) {
+        self.charger = charger;
+    }
+
+    /// Marks `provenance` with the replication of every outcall made for the call.
+    pub fn track(&mut self, provenance: Provenance) {
+        self.provenance.push(provenance);
+    }
+
+    /// Records every outcall made for the call in `log`.
+    pub fn log_outcalls(&mut self, log: OutcallLog) {
+        self.outcall_logs.push(log);
+    }
+
+    /// Records `outcall` in every log of the call.
+    pub fn record_outcall(&self, outcall: Outcall) {
+        for log in &self.outcall_logs {
+            log.record(outcall);
+        }
+    }
+
+    /// Combine the options of several calls into options for a single batched outcall.
+    ///
+    /// Response size limits and cycles add up when every call sets them. Otherwise they are left
+    /// unset, so that the transport default response size and the fee estimated for the whole batch
+    /// apply. The batch is capped at the lowest cycles cap and uncapped when any call is. The first
+    /// transform and charger set are used, headers are merged in order and every provenance and
+    /// outcall log is kept. The batch is only non-replicated when every call is.
+    pub fn combine<I: IntoIterator<Item = CallOptions>>(options: I) -> CallOptions {
+        let options: Vec<_> = options.into_iter().collect();
+        if options.is_empty() {
+            return CallOptions::default();
+        }
+        let sum = |field: fn(&CallOptions) -> Option<u64>| {
+            options
+                .iter()
+                .map(field)
+                .try_fold(0u64, |total, value| value.map(|value| total.saturating_add(value)))
+        };
+        let max_resp = sum(|options| options.max_resp);
+        let cycles = sum(|options| options.cycles);
+        let max_cycles = options
+            .iter()
+            .map(|options| options.max_cycles)
+            .try_fold(u64::MAX, |cap, value| value.map(|value| cap.min(value)));
+        let replication = if options
+            .iter()
+            .all(|options| options.replication == Replication::NonReplicated)
+        {
+            Replication::NonReplicated
+        } else {
+            Replication::Replicated
+        };
+        options.into_iter().fold(
+            CallOptions {
+                max_resp,
+                cycles,
+                max_cycles,
+                replication,
+                ..Default::default()
+            },
+            |mut combined, options| {
+                combined.transform = combined.transform.or(options.transform);
+                combined.charger = combined.charger.or(options.charger);
+                combined.provenance.extend(options.provenance);
+                combined.outcall_logs.extend(options.outcall_logs);
+                merge_headers(&mut combined.headers, options.headers);
+                combined
+            },
+        )
+    }
 }
 
 impl ICHttpClient {
     pub fn new(max_resp: Option<u64>) -> Self {
         ICHttpClient {
             max_response_bytes: if let Some(v) = max_resp { v } else { 500_000 },
+            subnet_size: DEFAULT_SUBNET_SIZE,
+            headers: vec![],
+            backend: Arc::new(IcOutcallBackend),
         }
     }
 
+    /// Makes outcalls with `backend` instead of the management canister.
+    pub fn set_backend(&mut self, backend: Arc<dyn HttpOutcallBackend>) {
+        self.backend = backend;
+    }
+
+    /// Sets a header sent with every request, replacing any previous value.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        merge_headers(
+            &mut self.headers,
+            vec![HttpHeader {
+                name: name.to_string(),
+                value: value.to_string(),
+            }],
+        );
+    }
+
+    /// Headers of a request: JSON content type, then the default headers, then the per-call ones.
+    fn headers_for(&self, options: &CallOptions) -> Vec<HttpHeader> {
+        let mut headers = json_headers();
+        merge_headers(&mut headers, self.headers.clone());
+        merge_headers(&mut headers, options.headers.clone());
+        headers
+    }
+
     pub fn set_max_response_bytes(&mut self, v: u64) {
         self.max_response_bytes = v;
     }
 
-    async fn request(
+    pub fn set_subnet_size(&mut self, v: u32) {
+        self.subnet_size = v;
+    }
+
+    fn request_argument(
         &self,
         url: String,
         req_type: HttpMethod,
         req_headers: Vec<HttpHeader>,
         payload: &Request,
-        options: CallOptions,
-    ) -> Result<Vec<u8>, String> {
-        let request = CanisterHttpRequestArgument {
-            url: url.clone(),
+        options: &CallOptions,
+    ) -> CanisterHttpRequestArgument {
+        CanisterHttpRequestArgument {
+            url,
             max_response_bytes: if let Some(v) = options.max_resp {
                 Some(v)
             } else {
@@ -64,64 +367,315 @@ impl ICHttpClient {
             //     principal: ic_cdk::api::id(),
             //     method: "transform".to_string(),
             // }))),
-            transform: match options.transform {
+            transform: match options.transform.clone() {
                 Some(t) => Some(t),
                 None => Some(TransformContext {
                     function: TransformFunc(candid::Func {
-                        principal: ic_cdk::api::id(),
+                        principal: self.backend.canister_id(),
                         method: "transform".to_string(),
                     }),
                     context: vec![],
                 }),
             },
+        }
+    }
+
+    /// Routes the response through the canister's dispatching transform function with `spec`,
+    /// unless `options` set a transform already.
+    pub fn with_transform_spec(&self, mut options: CallOptions, spec: &TransformSpec) -> CallOptions {
+        if options.transform.is_none() {
+            options.transform = Some(TransformContext {
+                function: TransformFunc(candid::Func {
+                    principal: self.backend.canister_id(),
+                    method: TRANSFORM_METHOD.to_string(),
+                }),
+                context: spec.encode(),
+            });
+        }
+        options
+    }
+
+    /// Cycles attached to an outcall: the `cycles` option when set, the fee for the subnet otherwise.
+    ///
+    /// A non-replicated outcall is charged like on a single-node subnet.
+    fn cycles_for(&self, request: &CanisterHttpRequestArgument, options: &CallOptions) -> u128 {
+        match (options.cycles, options.replication) {
+            (Some(cycles), _) => cycles as u128,
+            (None, Replication::Replicated) => http_request_required_cycles(request, self.subnet_size),
+            (None, Replication::NonReplicated) => http_request_required_cycles(request, 1),
+        }
+    }
+
+    /// Estimate the cycles a JSON-RPC `POST` outcall would cost, without making it.
+    pub fn estimate_cost(&self, url: String, payload: &Request, options: &CallOptions) -> u128 {
+        let request = self.request_argument(url, HttpMethod::POST, self.headers_for(options), payload, options);
+        self.cycles_for(&request, options)
+    }
+
+    /// Charges the cycles of a JSON-RPC `POST` outcall to the charger of `options`, if any.
+    ///
+    /// Outcalls refused for costing more than `max_cycles` aren't charged.
+    pub fn charge(&self, url: String, payload: &Request, options: &CallOptions) -> crate::Result<()> {
+        let charger = match &options.charger {
+            Some(charger) => charger,
+            None => return Ok(()),
         };
+        let cycles = self.estimate_cost(url, payload, options);
+        if options.max_cycles.is_some_and(|max_cycles| cycles > max_cycles as u128) {
+            return Ok(());
+        }
+        charger.charge(cycles)
+    }
+
+    async fn request(
+        &self,
+        url: String,
+        req_type: HttpMethod,
+        req_headers: Vec<HttpHeader>,
+        payload: &Request,
+        options: CallOptions,
+    ) -> Result<Vec<u8>, TransportError> {
+        let request = self.request_argument(url, req_type, req_headers, payload, &options);
 
-        let cycles = http_request_required_cycles(&request);
-        match http_request(request.clone(), cycles).await {
-            Ok((result,)) => Ok(result.body),
-            Err((r, m)) => {
-                let message = format!("The http_request resulted into error. RejectionCode: {r:?}, Error: {m}");
-                ic_cdk::api::print(message.clone());
-                Err(message)
+        let cycles = self.cycles_for(&request, &options);
+        if let Some(max_cycles) = options.max_cycles {
+            if cycles > max_cycles as u128 {
+                return Err(TransportError::Message(format!(
+                    "The http_request costs {cycles} cycles, more than the allowed {max_cycles}"
+                )));
             }
         }
+        for provenance in &options.provenance {
+            provenance.mark(options.replication);
+        }
+        let response = match options.replication {
+            Replication::Replicated => self.backend.http_request(request, cycles),
+            Replication::NonReplicated => self.backend.http_request_non_replicated(request, cycles),
+        };
+        let response = response.await;
+        options.record_outcall(Outcall {
+            cycles,
+            response_bytes: response.as_ref().ok().map(|(result,)| response_size(result)),
+        });
+        match response {
+            Ok((result,)) if result.status == 200u8 => Ok(result.body),
+            Ok((result,)) => Err(TransportError::Code(
+                u16::try_from(&result.status.0).unwrap_or(u16::MAX),
+            )),
+            Err((r, m)) => Err(TransportError::Rejected(r, m)),
+        }
     }
 
-    pub async fn get(&self, url: String, payload: &Request, options: CallOptions) -> Result<Vec<u8>, String> {
-        let request_headers = vec![HttpHeader {
-            name: "Content-Type".to_string(),
-            value: "application/json".to_string(),
-        }];
+    pub async fn get(&self, url: String, payload: &Request, options: CallOptions) -> Result<Vec<u8>, TransportError> {
+        self.request(url, HttpMethod::GET, self.headers_for(&options), payload, options)
+            .await
+    }
 
-        self.request(url, HttpMethod::GET, request_headers, payload, options)
+    pub async fn post(&self, url: String, payload: &Request, options: CallOptions) -> Result<Vec<u8>, TransportError> {
+        self.request(url, HttpMethod::POST, self.headers_for(&options), payload, options)
             .await
     }
+}
 
-    pub async fn post(&self, url: String, payload: &Request, options: CallOptions) -> Result<Vec<u8>, String> {
-        let request_headers = vec![HttpHeader {
-            name: "Content-Type".to_string(),
-            value: "application/json".to_string(),
-        }];
+fn json_headers() -> Vec<HttpHeader> {
+    vec![HttpHeader {
+        name: "Content-Type".to_string(),
+        value: "application/json".to_string(),
+    }]
+}
 
-        self.request(url, HttpMethod::POST, request_headers, payload, options)
-            .await
+/// Adds `overrides` to `headers`, replacing headers of the same name (case-insensitive).
+fn merge_headers(headers: &mut Vec<HttpHeader>, overrides: Vec<HttpHeader>) {
+    for header in overrides {
+        headers.retain(|h| !h.name.eq_ignore_ascii_case(&header.name));
+        headers.push(header);
     }
 }
 
+/// Size of the body and headers of `response`, as counted against `max_response_bytes`.
+fn response_size(response: &HttpResponse) -> u64 {
+    let headers: usize = response
+        .headers
+        .iter()
+        .map(|header| header.name.len() + header.value.len())
+        .sum();
+    (headers + response.body.len()) as u64
+}
+
+fn header_names(headers: &[HttpHeader]) -> Vec<&str> {
+    headers.iter().map(|h| h.name.as_str()).collect()
+}
+
 // Calcurate cycles for http_request
 // NOTE:
 //   v0.11: https://github.com/dfinity/cdk-rs/blob/0b14facb80e161de79264c8f88b1a0c8e18ffcb6/examples/management_canister/src/caller/lib.rs#L7-L19
 //   v0.8: https://github.com/dfinity/cdk-rs/blob/a8454cb37420c200c7b224befd6f68326a01442e/src/ic-cdk/src/api/management_canister/http_request.rs#L290-L299
-fn http_request_required_cycles(arg: &CanisterHttpRequestArgument) -> u128 {
+fn http_request_required_cycles(arg: &CanisterHttpRequestArgument, subnet_size: u32) -> u128 {
     let max_response_bytes = match arg.max_response_bytes {
         Some(ref n) => *n as u128,
         None => 2 * 1024 * 1024u128, // default 2MiB
     };
     let arg_raw = candid::utils::encode_args((arg,)).expect("Failed to encode arguments.");
-    // The fee is for a 13-node subnet to demonstrate a typical usage.
-    (3_000_000u128
-        + 60_000u128 * 13
-        + (arg_raw.len() as u128 + "http_request".len() as u128) * 400
-        + max_response_bytes * 800)
-        * 13
+    http_request_cost(
+        arg_raw.len() as u128 + "http_request".len() as u128,
+        max_response_bytes,
+        subnet_size,
+    )
+}
+
+/// Fee in cycles of an HTTPS outcall on a subnet of `subnet_size` nodes.
+pub fn http_request_cost(request_bytes: u128, max_response_bytes: u128, subnet_size: u32) -> u128 {
+    let n = subnet_size as u128;
+    (3_000_000u128 + 60_000u128 * n + request_bytes * 400 + max_response_bytes * 800) * n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compute_fee_for_a_13_node_subnet() {
+        // 3_000_000 + 60_000 * 13 + 100 * 400 + 1_000 * 800 = 4_620_000
+        assert_eq!(http_request_cost(100, 1_000, 13), 4_620_000 * 13);
+    }
+
+    #[test]
+    fn should_scale_fee_with_subnet_size() {
+        // 3_000_000 + 60_000 * 34 + 100 * 400 + 1_000 * 800 = 5_880_000
+        assert_eq!(http_request_cost(100, 1_000, 34), 5_880_000 * 34);
+    }
+
+    #[test]
+    fn should_attach_explicit_cycles() {
+        let client = ICHttpClient::new(None);
+        let request = jsonrpc_core::Request::Single(crate::helpers::build_request(1, "eth_chainId", vec![]));
+        let transform = Some(TransformContext {
+            function: TransformFunc(candid::Func {
+                principal: Principal::anonymous(),
+                method: "transform".to_string(),
+            }),
+            context: vec![],
+        });
+        let computed = CallOptionsBuilder::default()
+            .max_resp(None)
+            .cycles(None)
+            .transform(transform.clone())
+            .build()
+            .unwrap();
+        let explicit = CallOptionsBuilder::default()
+            .max_resp(None)
+            .cycles(Some(42))
+            .transform(transform)
+            .build()
+            .unwrap();
+
+        assert!(client.estimate_cost("https://example.com".into(), &request, &computed) > 500_000 * 800 * 13);
+        assert_eq!(
+            client.estimate_cost("https://example.com".into(), &request, &explicit),
+            42
+        );
+    }
+
+    #[test]
+    fn should_charge_non_replicated_outcalls_like_a_single_node() {
+        let mut client = ICHttpClient::new(None);
+        client.set_backend(Arc::new(crate::transports::NativeOutcallBackend::new(|_| {
+            HttpResponse::default()
+        })));
+        let request = jsonrpc_core::Request::Single(crate::helpers::build_request(1, "eth_chainId", vec![]));
+        let replicated = CallOptions::default();
+        let mut non_replicated = CallOptions::default();
+        non_replicated.set_replication(Replication::NonReplicated);
+
+        let replicated = client.estimate_cost("https://example.com".into(), &request, &replicated);
+        let non_replicated = client.estimate_cost("https://example.com".into(), &request, &non_replicated);
+
+        assert!(non_replicated * 13 < replicated);
+        assert_eq!(
+            CallOptions::combine(vec![CallOptions::default(), {
+                let mut options = CallOptions::default();
+                options.set_replication(Replication::NonReplicated);
+                options
+            }])
+            .replication(),
+            Replication::Replicated
+        );
+    }
+
+    #[test]
+    fn should_override_default_headers_per_call() {
+        let mut client = ICHttpClient::new(None);
+        client.set_header("x-api-key", "default-key");
+        client.set_header("Authorization", "Bearer default-token");
+        let options = CallOptionsBuilder::default()
+            .max_resp(None)
+            .cycles(None)
+            .transform(None)
+            .headers(vec![HttpHeader {
+                name: "authorization".to_string(),
+                value: "Bearer secret".to_string(),
+            }])
+            .build()
+            .unwrap();
+
+        let headers = client
+            .headers_for(&options)
+            .into_iter()
+            .map(|h| (h.name, h.value))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            headers,
+            vec![
+                ("Content-Type".to_string(), "application/json".to_string()),
+                ("x-api-key".to_string(), "default-key".to_string()),
+                ("authorization".to_string(), "Bearer secret".to_string()),
+            ]
+        );
+        assert!(!format!("{:?}", client).contains("default-"));
+        assert!(!format!("{:?}", options).contains("secret"));
+    }
+
+    #[test]
+    fn should_combine_options_of_batched_calls() {
+        let options = |max_resp, cycles| {
+            CallOptionsBuilder::default()
+                .max_resp(max_resp)
+                .cycles(cycles)
+                .transform(None)
+                .build()
+                .unwrap()
+        };
+
+        let combined = CallOptions::combine(vec![options(Some(1_000), Some(5)), options(Some(2_000), Some(10))]);
+
+        assert_eq!(combined, options(Some(3_000), Some(15)));
+        assert_eq!(CallOptions::combine(vec![]), CallOptions::default());
+    }
+
+    #[test]
+    fn should_leave_options_unset_when_some_batched_calls_leave_them_unset() {
+        let options = |max_resp, cycles, max_cycles| {
+            CallOptionsBuilder::default()
+                .max_resp(max_resp)
+                .cycles(cycles)
+                .transform(None)
+                .max_cycles(max_cycles)
+                .build()
+                .unwrap()
+        };
+
+        let combined = CallOptions::combine(vec![
+            options(Some(1_000), None, Some(300)),
+            options(None, Some(10), Some(100)),
+            options(Some(2_000), Some(20), Some(200)),
+        ]);
+        assert_eq!(combined, options(None, None, Some(100)));
+
+        let combined = CallOptions::combine(vec![
+            options(Some(1_000), Some(10), Some(100)),
+            options(Some(2_000), Some(20), None),
+        ]);
+        assert_eq!(combined, options(Some(3_000), Some(30), None));
+    }
 }
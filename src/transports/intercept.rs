@@ -0,0 +1,273 @@
+//! Interceptor Transport
+
+use crate::{
+    error::Result,
+    metering::{Charging, CycleCost},
+    rpc, BatchTransport, RequestId, Transport,
+};
+use futures::future::{BoxFuture, FutureExt};
+use std::{fmt, sync::Arc};
+
+use super::ic_http_client::CallOptions;
+
+/// Middleware seeing, and possibly modifying, every call and its outcome.
+///
+/// Both hooks do nothing by default, so interceptors only implement the ones they need.
+pub trait Interceptor: fmt::Debug + Send + Sync {
+    /// Called with the call produced by `Transport::prepare` before it's sent.
+    fn on_request(&self, id: RequestId, call: &mut rpc::Call) {}
+
+    /// Called with the value or error of a call before it's decoded.
+    fn on_response(&self, id: RequestId, call: &rpc::Call, result: &mut Result<rpc::Value>) {}
+}
+
+/// Transport wrapper running a chain of [`Interceptor`]s around an inner transport.
+///
+/// Requests go through the interceptors in the order they were added, responses in reverse order.
+#[derive(Debug, Clone)]
+pub struct Intercepted<T> {
+    transport: T,
+    interceptors: Vec<Arc<dyn Interceptor>>,
+}
+
+impl<T: Transport> Intercepted<T> {
+    /// Wraps `transport` without any interceptor.
+    pub fn new(transport: T) -> Self {
+        Intercepted {
+            transport,
+            interceptors: vec![],
+        }
+    }
+
+    /// Adds `interceptor` at the end of the chain.
+    pub fn with<I: Interceptor + 'static>(self, interceptor: I) -> Self {
+        self.with_arc(Arc::new(interceptor))
+    }
+
+    /// Adds a shared `interceptor` at the end of the chain.
+    pub fn with_arc(mut self, interceptor: Arc<dyn Interceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Borrows the inner transport.
+    pub fn inner(&self) -> &T {
+        &self.transport
+    }
+
+    fn intercept_request(&self, id: RequestId, call: &mut rpc::Call) {
+        for interceptor in &self.interceptors {
+            interceptor.on_request(id, call);
+        }
+    }
+}
+
+fn intercept_response(
+    interceptors: &[Arc<dyn Interceptor>],
+    id: RequestId,
+    call: &rpc::Call,
+    mut result: Result<rpc::Value>,
+) -> Result<rpc::Value> {
+    for interceptor in interceptors.iter().rev() {
+        interceptor.on_response(id, call, &mut result);
+    }
+    result
+}
+
+// Interceptors aren't run for an estimate since they may have side effects, and the requests
+// they rewrite cost about the same.
+impl<T: CycleCost> CycleCost for Intercepted<T> {
+    fn cycle_cost(&self, request: &rpc::Request, options: &CallOptions) -> u128 {
+        self.transport.cycle_cost(request, options)
+    }
+}
+
+impl<T: Charging> Charging for Intercepted<T> {}
+
+impl<T> Transport for Intercepted<T>
+where
+    T: Transport,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.transport.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, mut request: rpc::Call, options: CallOptions) -> Self::Out {
+        self.intercept_request(id, &mut request);
+        let interceptors = self.interceptors.clone();
+        self.transport
+            .send(id, request.clone(), options)
+            .map(move |result| intercept_response(&interceptors, id, &request, result))
+            .boxed()
+    }
+
+    fn set_max_response_bytes(&mut self, v: u64) {
+        self.transport.set_max_response_bytes(v);
+    }
+}
+
+impl<T> BatchTransport for Intercepted<T>
+where
+    T: BatchTransport,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, Result<Vec<Result<rpc::Value>>>>;
+
+    fn send_batch<I>(&self, requests: I, options: CallOptions) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let requests: Vec<_> = requests
+            .into_iter()
+            .map(|(id, mut call)| {
+                self.intercept_request(id, &mut call);
+                (id, call)
+            })
+            .collect();
+        let interceptors = self.interceptors.clone();
+        self.transport
+            .send_batch(requests.clone(), options)
+            .map(move |results| {
+                Ok(requests
+                    .iter()
+                    .zip(results?)
+                    .map(|((id, call), result)| intercept_response(&interceptors, *id, call, result))
+                    .collect())
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::Error,
+        transports::{
+            replay::{Interaction, Response},
+            Batch, Either, Replay,
+        },
+        types::U64,
+        Web3,
+    };
+    use parking_lot::Mutex;
+
+    /// Replaces the first param of `eth_getBalance` with a fixed address.
+    #[derive(Debug)]
+    struct InjectAddress;
+
+    impl Interceptor for InjectAddress {
+        fn on_request(&self, _id: RequestId, call: &mut rpc::Call) {
+            match call {
+                rpc::Call::MethodCall(call) if call.method == "eth_getBalance" => {
+                    if let rpc::Params::Array(params) = &mut call.params {
+                        params[0] = "0x0000000000000000000000000000000000000001".into();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Turns reverts into `Error::InvalidResponse`.
+    #[derive(Debug)]
+    struct RewriteReverts;
+
+    impl Interceptor for RewriteReverts {
+        fn on_response(&self, _id: RequestId, _call: &rpc::Call, result: &mut Result<rpc::Value>) {
+            if let Err(Error::Rpc(err)) = result {
+                if err.code == rpc::ErrorCode::ServerError(3) {
+                    *result = Err(Error::InvalidResponse(err.message.clone()));
+                }
+            }
+        }
+    }
+
+    #[derive(Debug, Default, Clone)]
+    struct Log(Arc<Mutex<Vec<String>>>);
+
+    impl Interceptor for Log {
+        fn on_request(&self, id: RequestId, call: &mut rpc::Call) {
+            let (method, _) = crate::helpers::method_and_params(call);
+            self.0.lock().push(format!("-> {} {}", id, method));
+        }
+
+        fn on_response(&self, id: RequestId, _call: &rpc::Call, result: &mut Result<rpc::Value>) {
+            self.0.lock().push(format!("<- {} {}", id, result.is_ok()));
+        }
+    }
+
+    fn node() -> Replay {
+        Replay::new(vec![
+            Interaction {
+                method: "eth_getBalance".into(),
+                params: serde_json::json!(["0x0000000000000000000000000000000000000001", "latest"]),
+                response: Response::Result("0x10".into()),
+            },
+            Interaction {
+                method: "eth_blockNumber".into(),
+                params: serde_json::json!([]),
+                response: Response::Result("0x20".into()),
+            },
+            Interaction {
+                method: "eth_call".into(),
+                params: serde_json::json!([{"to": "0x0000000000000000000000000000000000000002"}, "latest"]),
+                response: Response::Error(rpc::Error {
+                    code: rpc::ErrorCode::ServerError(3),
+                    message: "execution reverted".into(),
+                    data: None,
+                }),
+            },
+        ])
+    }
+
+    #[test]
+    fn should_modify_requests_and_responses() {
+        let log = Log::default();
+        let web3 = Web3::new(node()).with_interceptors(vec![
+            Arc::new(log.clone()) as Arc<dyn Interceptor>,
+            Arc::new(InjectAddress),
+            Arc::new(RewriteReverts),
+        ]);
+
+        let balance = futures::executor::block_on(web3.eth().balance(
+            crate::types::Address::from_low_u64_be(9),
+            None,
+            CallOptions::default(),
+        ));
+        let call = futures::executor::block_on(web3.transport().execute(
+            "eth_call",
+            vec![
+                serde_json::json!({"to": "0x0000000000000000000000000000000000000002"}),
+                "latest".into(),
+            ],
+            CallOptions::default(),
+        ));
+
+        assert_eq!(balance, Ok(16.into()));
+        assert_eq!(call, Err(Error::InvalidResponse("execution reverted".into())));
+        assert_eq!(
+            *log.0.lock(),
+            vec!["-> 0 eth_getBalance", "<- 0 true", "-> 1 eth_call", "<- 1 false"]
+        );
+    }
+
+    #[test]
+    fn should_compose_with_batch_and_either() {
+        let log = Log::default();
+        let transport = Intercepted::new(Batch::new(Either::<_, Replay>::Left(node()))).with(log.clone());
+        let web3 = Web3::new(transport.clone());
+
+        let block_number = web3.eth().block_number(CallOptions::default());
+        let submitted = transport.inner().submit_batch();
+        let (block_number, submitted) = futures::executor::block_on(futures::future::join(block_number, submitted));
+
+        assert_eq!(block_number, Ok(U64::from(0x20)));
+        assert!(submitted.is_ok());
+        assert_eq!(*log.0.lock(), vec!["-> 0 eth_blockNumber", "<- 0 true"]);
+    }
+}
@@ -0,0 +1,531 @@
+//! Metrics collecting Transport
+
+use crate::{
+    error::{Error, Result, TransportError},
+    helpers,
+    metering::Charging,
+    rpc, BatchTransport, RequestId, Transport,
+};
+use candid::{CandidType, Deserialize};
+use futures::future::{BoxFuture, FutureExt};
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, fmt::Write, sync::Arc};
+
+use super::{
+    ic_http_client::{CallOptions, OutcallLog},
+    ICHttp,
+};
+
+/// Method label of batch requests.
+pub const BATCH_METHOD: &str = "batch";
+
+const RESPONSE_BYTES_BUCKETS: [u64; 8] = [256, 1_024, 4_096, 16_384, 65_536, 262_144, 1_048_576, 2_000_000];
+const INSTRUCTIONS_BUCKETS: [u64; 6] = [
+    100_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+    1_000_000_000,
+    10_000_000_000,
+];
+
+/// Distribution of observed values over fixed buckets.
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    /// Upper bounds of the buckets and the number of values at or below them, not cumulative.
+    pub buckets: Vec<(u64, u64)>,
+    pub sum: u128,
+    pub count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &[u64]) -> Self {
+        Histogram {
+            buckets: bounds.iter().map(|bound| (*bound, 0)).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn observe(&mut self, value: u64) {
+        if let Some((_, count)) = self.buckets.iter_mut().find(|(bound, _)| value <= *bound) {
+            *count += 1;
+        }
+        self.sum += value as u128;
+        self.count += 1;
+    }
+}
+
+/// Failures of one kind, e.g. `rejection` with the `RejectionCode` or `rpc` with the JSON-RPC error code.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FailureCount {
+    pub kind: String,
+    pub code: String,
+    pub count: u64,
+}
+
+/// Metrics of one JSON-RPC method sent to one endpoint.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MethodMetrics {
+    pub method: String,
+    pub endpoint: String,
+    pub calls: u64,
+    pub failures: Vec<FailureCount>,
+    /// Cycles attached to every outcall made, retries and failovers included.
+    pub cycles: u128,
+    /// Size of the HTTP responses of every outcall made, body and headers.
+    pub response_bytes: Histogram,
+    /// Instructions executed by the call context while the call was pending.
+    pub instructions: Histogram,
+}
+
+/// Metrics of every method and endpoint.
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub methods: Vec<MethodMetrics>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    calls: u64,
+    failures: BTreeMap<(String, String), u64>,
+    cycles: u128,
+    response_bytes: Histogram,
+    instructions: Histogram,
+}
+
+impl Default for Entry {
+    fn default() -> Self {
+        Entry {
+            calls: 0,
+            failures: BTreeMap::new(),
+            cycles: 0,
+            response_bytes: Histogram::new(&RESPONSE_BYTES_BUCKETS),
+            instructions: Histogram::new(&INSTRUCTIONS_BUCKETS),
+        }
+    }
+}
+
+impl Entry {
+    fn record_failure(&mut self, err: &Error) {
+        let failure = match err {
+            Error::Transport(TransportError::Rejected(code, _)) => ("rejection".to_string(), format!("{:?}", code)),
+            Error::Transport(TransportError::Code(status)) => ("http".to_string(), status.to_string()),
+            Error::Rpc(err) => ("rpc".to_string(), err.code.code().to_string()),
+            _ => ("other".to_string(), String::new()),
+        };
+        *self.failures.entry(failure).or_default() += 1;
+    }
+
+    fn record_result(&mut self, result: &Result<rpc::Value>) {
+        if let Err(err) = result {
+            self.record_failure(err);
+        }
+    }
+
+    fn record_outcalls(&mut self, log: &OutcallLog) {
+        for outcall in log.outcalls() {
+            self.cycles += outcall.cycles;
+            if let Some(bytes) = outcall.response_bytes {
+                self.response_bytes.observe(bytes);
+            }
+        }
+    }
+}
+
+/// Collects metrics of the transports wrapped with it.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsRegistry {
+    entries: Arc<Mutex<BTreeMap<(String, String), Entry>>>,
+}
+
+impl MetricsRegistry {
+    /// Wraps `transport`, labelling its metrics with `endpoint`.
+    pub fn wrap<T>(&self, endpoint: &str, transport: T) -> Metrics<T> {
+        Metrics {
+            transport,
+            endpoint: endpoint.to_string(),
+            registry: self.clone(),
+        }
+    }
+
+    /// Wraps an `ICHttp` transport, labelling its metrics with its URL.
+    pub fn wrap_http(&self, transport: ICHttp) -> Metrics<ICHttp> {
+        let endpoint = transport.url().to_string();
+        self.wrap(&endpoint, transport)
+    }
+
+    /// Forgets every metric.
+    pub fn reset(&self) {
+        self.entries.lock().clear();
+    }
+
+    /// Candid serializable copy of the metrics.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let methods = self
+            .entries
+            .lock()
+            .iter()
+            .map(|((method, endpoint), entry)| MethodMetrics {
+                method: method.clone(),
+                endpoint: endpoint.clone(),
+                calls: entry.calls,
+                failures: entry
+                    .failures
+                    .iter()
+                    .map(|((kind, code), count)| FailureCount {
+                        kind: kind.clone(),
+                        code: code.clone(),
+                        count: *count,
+                    })
+                    .collect(),
+                cycles: entry.cycles,
+                response_bytes: entry.response_bytes.clone(),
+                instructions: entry.instructions.clone(),
+            })
+            .collect();
+        MetricsSnapshot { methods }
+    }
+
+    /// The metrics in Prometheus text exposition format, e.g. for a canister's `http_request` query.
+    pub fn to_prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut out = String::new();
+        let labels =
+            |m: &MethodMetrics| format!("method=\"{}\",endpoint=\"{}\"", escape(&m.method), escape(&m.endpoint));
+
+        header(&mut out, "ic_web3_rpc_calls_total", "counter", "JSON-RPC calls sent.");
+        for m in &snapshot.methods {
+            let _ = writeln!(out, "ic_web3_rpc_calls_total{{{}}} {}", labels(m), m.calls);
+        }
+        header(
+            &mut out,
+            "ic_web3_rpc_failures_total",
+            "counter",
+            "JSON-RPC calls that failed.",
+        );
+        for m in &snapshot.methods {
+            for failure in &m.failures {
+                let _ = writeln!(
+                    out,
+                    "ic_web3_rpc_failures_total{{{},kind=\"{}\",code=\"{}\"}} {}",
+                    labels(m),
+                    escape(&failure.kind),
+                    escape(&failure.code),
+                    failure.count
+                );
+            }
+        }
+        header(
+            &mut out,
+            "ic_web3_rpc_cycles_total",
+            "counter",
+            "Cycles attached to outcalls, retries included.",
+        );
+        for m in &snapshot.methods {
+            let _ = writeln!(out, "ic_web3_rpc_cycles_total{{{}}} {}", labels(m), m.cycles);
+        }
+        header(
+            &mut out,
+            "ic_web3_rpc_response_bytes",
+            "histogram",
+            "Size of outcall responses, body and headers.",
+        );
+        for m in &snapshot.methods {
+            write_histogram(&mut out, "ic_web3_rpc_response_bytes", &labels(m), &m.response_bytes);
+        }
+        header(
+            &mut out,
+            "ic_web3_rpc_instructions",
+            "histogram",
+            "Instructions executed while calls were pending.",
+        );
+        for m in &snapshot.methods {
+            write_histogram(&mut out, "ic_web3_rpc_instructions", &labels(m), &m.instructions);
+        }
+        out
+    }
+
+    fn record<F: FnOnce(&mut Entry)>(&self, method: &str, endpoint: &str, f: F) {
+        let mut entries = self.entries.lock();
+        f(entries.entry((method.to_string(), endpoint.to_string())).or_default());
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn write_histogram(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    let mut cumulative = 0;
+    for (bound, count) in &histogram.buckets {
+        cumulative += count;
+        let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulative);
+    }
+    let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, histogram.count);
+    let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+    let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Transport wrapper recording per-method metrics in a [`MetricsRegistry`].
+#[derive(Debug, Clone)]
+pub struct Metrics<T> {
+    transport: T,
+    endpoint: String,
+    registry: MetricsRegistry,
+}
+
+impl<T: Charging> Charging for Metrics<T> {}
+
+impl<T> Transport for Metrics<T>
+where
+    T: Transport,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.transport.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call, mut options: CallOptions) -> Self::Out {
+        let (method, _) = helpers::method_and_params(&request);
+        let log = OutcallLog::new();
+        options.log_outcalls(log.clone());
+        let registry = self.registry.clone();
+        let endpoint = self.endpoint.clone();
+        let instructions = helpers::call_context_instructions();
+        self.transport
+            .send(id, request, options)
+            .map(move |result| {
+                registry.record(&method, &endpoint, |entry| {
+                    entry.calls += 1;
+                    entry.record_outcalls(&log);
+                    entry
+                        .instructions
+                        .observe(helpers::call_context_instructions().saturating_sub(instructions));
+                    entry.record_result(&result);
+                });
+                result
+            })
+            .boxed()
+    }
+
+    fn set_max_response_bytes(&mut self, v: u64) {
+        self.transport.set_max_response_bytes(v);
+    }
+}
+
+/// Batches are recorded as a single call to the [`BATCH_METHOD`], each failed element counting as a failure.
+impl<T> BatchTransport for Metrics<T>
+where
+    T: BatchTransport,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, Result<Vec<Result<rpc::Value>>>>;
+
+    fn send_batch<I>(&self, requests: I, mut options: CallOptions) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let log = OutcallLog::new();
+        options.log_outcalls(log.clone());
+        let registry = self.registry.clone();
+        let endpoint = self.endpoint.clone();
+        let instructions = helpers::call_context_instructions();
+        self.transport
+            .send_batch(requests, options)
+            .map(move |results| {
+                registry.record(BATCH_METHOD, &endpoint, |entry| {
+                    entry.calls += 1;
+                    entry.record_outcalls(&log);
+                    entry
+                        .instructions
+                        .observe(helpers::call_context_instructions().saturating_sub(instructions));
+                    match &results {
+                        Ok(results) => {
+                            for result in results {
+                                entry.record_result(result);
+                            }
+                        }
+                        Err(err) => entry.record_failure(err),
+                    }
+                });
+                results
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        metering::CycleCost,
+        transports::{NativeOutcallBackend, RetryPolicy},
+    };
+    use ic_cdk::api::management_canister::http_request::{
+        CanisterHttpRequestArgument, HttpHeader, HttpResponse, TransformArgs,
+    };
+
+    fn node(request: &CanisterHttpRequestArgument) -> HttpResponse {
+        let call: serde_json::Value = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+        let (status, body) = match call["method"].as_str() {
+            Some("eth_chainId") => (
+                200u16,
+                serde_json::json!({"jsonrpc": "2.0", "id": call["id"], "result": "0x1"}),
+            ),
+            Some("eth_call") => (
+                200,
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": call["id"],
+                    "error": {"code": 3, "message": "execution reverted"}
+                }),
+            ),
+            _ => (503, serde_json::Value::Null),
+        };
+        HttpResponse {
+            status: status.into(),
+            headers: vec![],
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    fn registry_with_calls() -> MetricsRegistry {
+        let registry = MetricsRegistry::default();
+        let transport = registry.wrap_http(ICHttp::new("https://rpc.example.com", None).unwrap().with_backend(
+            NativeOutcallBackend::new(node).with_transform("transform", |args: TransformArgs| args.response),
+        ));
+        for method in ["eth_chainId", "eth_chainId", "eth_call", "eth_gasPrice"] {
+            let _ = futures::executor::block_on(transport.execute(method, vec![], CallOptions::default()));
+        }
+        registry
+    }
+
+    #[test]
+    fn should_collect_metrics_per_method() {
+        let snapshot = registry_with_calls().snapshot();
+
+        let methods: Vec<_> = snapshot
+            .methods
+            .iter()
+            .map(|m| (m.method.as_str(), m.calls, m.failures.clone()))
+            .collect();
+        assert_eq!(
+            methods,
+            vec![
+                (
+                    "eth_call",
+                    1,
+                    vec![FailureCount {
+                        kind: "rpc".into(),
+                        code: "3".into(),
+                        count: 1
+                    }]
+                ),
+                ("eth_chainId", 2, vec![]),
+                (
+                    "eth_gasPrice",
+                    1,
+                    vec![FailureCount {
+                        kind: "http".into(),
+                        code: "503".into(),
+                        count: 1
+                    }]
+                ),
+            ]
+        );
+        let chain_id = &snapshot.methods[1];
+        assert_eq!(chain_id.endpoint, "https://rpc.example.com");
+        assert!(chain_id.cycles > 0);
+        assert_eq!(chain_id.response_bytes.count, 2);
+        assert_eq!(
+            chain_id.response_bytes.sum,
+            2 * r#"{"id":0,"jsonrpc":"2.0","result":"0x1"}"#.len() as u128
+        );
+    }
+
+    #[test]
+    fn should_record_every_outcall_attempt() {
+        let registry = MetricsRegistry::default();
+        let http = || {
+            ICHttp::new("https://rpc.example.com", None).unwrap().with_backend(
+                NativeOutcallBackend::new(|_: &CanisterHttpRequestArgument| HttpResponse {
+                    status: 503u16.into(),
+                    headers: vec![HttpHeader {
+                        name: "Retry-After".into(),
+                        value: "1".into(),
+                    }],
+                    body: b"unavailable".to_vec(),
+                })
+                .with_transform("transform", |args: TransformArgs| args.response),
+            )
+        };
+        let (_, call) = http().prepare("eth_gasPrice", vec![]);
+        let outcall_cycles = http().cycle_cost(&rpc::Request::Single(call), &CallOptions::default());
+        let transport = registry.wrap_http(http().with_retry_policy(RetryPolicy::default()));
+
+        let _ = futures::executor::block_on(transport.execute("eth_gasPrice", vec![], CallOptions::default()));
+
+        let gas_price = &registry.snapshot().methods[0];
+        assert_eq!(gas_price.calls, 1);
+        assert_eq!(gas_price.cycles, 3 * outcall_cycles);
+        assert_eq!(gas_price.response_bytes.count, 3);
+        assert_eq!(
+            gas_price.response_bytes.sum,
+            3 * "Retry-After1unavailable".len() as u128
+        );
+    }
+
+    #[test]
+    fn should_render_prometheus_text() {
+        let text = registry_with_calls().to_prometheus();
+
+        assert!(text.contains("# TYPE ic_web3_rpc_calls_total counter\n"));
+        assert!(
+            text.contains("ic_web3_rpc_calls_total{method=\"eth_chainId\",endpoint=\"https://rpc.example.com\"} 2\n")
+        );
+        assert!(text.contains(
+            "ic_web3_rpc_failures_total{method=\"eth_gasPrice\",endpoint=\"https://rpc.example.com\",kind=\"http\",code=\"503\"} 1\n"
+        ));
+        assert!(text.contains(
+            "ic_web3_rpc_response_bytes_bucket{method=\"eth_chainId\",endpoint=\"https://rpc.example.com\",le=\"256\"} 2\n"
+        ));
+        assert!(text.contains(
+            "ic_web3_rpc_response_bytes_count{method=\"eth_chainId\",endpoint=\"https://rpc.example.com\"} 2\n"
+        ));
+    }
+
+    #[test]
+    fn should_record_batches_as_one_call() {
+        let registry = MetricsRegistry::default();
+        let transport = registry.wrap_http(
+            ICHttp::new("https://rpc.example.com", None).unwrap().with_backend(
+                NativeOutcallBackend::new(|_: &CanisterHttpRequestArgument| HttpResponse {
+                    status: 200u8.into(),
+                    headers: vec![],
+                    body: br#"[{"jsonrpc":"2.0","id":0,"result":"0x1"},{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"x"}}]"#.to_vec(),
+                })
+                .with_transform("transform", |args: TransformArgs| args.response),
+            ),
+        );
+        let requests = vec![
+            transport.prepare("eth_chainId", vec![]),
+            transport.prepare("eth_call", vec![]),
+        ];
+
+        let _ = futures::executor::block_on(transport.send_batch(requests, CallOptions::default()));
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.methods.len(), 1);
+        assert_eq!(snapshot.methods[0].method, BATCH_METHOD);
+        assert_eq!(snapshot.methods[0].calls, 1);
+        assert_eq!(snapshot.methods[0].failures[0].count, 1);
+    }
+}
@@ -3,18 +3,38 @@
 pub mod batch;
 
 pub use self::batch::Batch;
+pub mod cache;
+pub use self::cache::Cache;
 pub mod either;
 pub use self::either::Either;
+pub mod evm_rpc;
+pub use self::evm_rpc::EvmRpc;
+pub mod failover;
+pub use self::failover::Failover;
 
-#[cfg(any(feature = "http", feature = "http-rustls"))]
+#[cfg(feature = "http")]
 pub mod http;
-#[cfg(any(feature = "http", feature = "http-rustls"))]
+#[cfg(feature = "http")]
 pub use self::http::Http;
 
 pub mod ic_http_client;
 pub use self::ic_http_client::ICHttpClient;
 pub mod ic_http;
 pub use self::ic_http::ICHttp;
+pub mod intercept;
+pub use self::intercept::{Intercepted, Interceptor};
+pub mod metrics;
+pub use self::metrics::{Metrics, MetricsRegistry};
+pub mod outcall;
+pub use self::outcall::{HttpOutcallBackend, IcOutcallBackend, NativeOutcallBackend};
+pub mod pinned;
+pub use self::pinned::{Head, Pinned};
+pub mod quorum;
+pub use self::quorum::Quorum;
+pub mod replay;
+pub use self::replay::{Recorder, Replay};
+pub mod retry;
+pub use self::retry::RetryPolicy;
 
 #[cfg(any(feature = "ws-tokio", feature = "ws-async-std"))]
 pub mod ws;
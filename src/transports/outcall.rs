@@ -0,0 +1,220 @@
+//! Backends performing the HTTPS outcalls of `ICHttpClient`.
+
+use candid::{CandidType, Principal};
+use futures::future::{self, BoxFuture, FutureExt};
+use ic_cdk::api::{
+    call::{call_with_payment128, CallResult, RejectionCode},
+    management_canister::http_request::{
+        http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+        TransformContext,
+    },
+};
+use parking_lot::Mutex;
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use crate::transforms::transform::TransformProcessor;
+
+/// Largest response the management canister accepts when `max_response_bytes` isn't set.
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 2 * 1024 * 1024;
+
+/// Performs HTTPS outcalls on behalf of `ICHttpClient`.
+///
+/// Inside a canister this is [`IcOutcallBackend`], which calls the management canister. Outside of
+/// the IC [`NativeOutcallBackend`] emulates it, so that the whole `ICHttp` stack runs under `cargo test`.
+pub trait HttpOutcallBackend: fmt::Debug + Send + Sync {
+    /// Principal of the canister making the outcall, owner of the default `transform` function.
+    fn canister_id(&self) -> Principal;
+
+    /// Makes the outcall, attaching `cycles`.
+    fn http_request(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> BoxFuture<'static, CallResult<(HttpResponse,)>>;
+
+    /// Makes the outcall from a single node, attaching `cycles`.
+    ///
+    /// Backends without a notion of replication make a regular outcall.
+    fn http_request_non_replicated(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> BoxFuture<'static, CallResult<(HttpResponse,)>> {
+        self.http_request(request, cycles)
+    }
+}
+
+/// Argument of the management canister's `http_request` with the `is_replicated` flag, which
+/// `CanisterHttpRequestArgument` lacks.
+#[derive(CandidType, Clone, Debug)]
+struct NonReplicatedRequestArgument {
+    url: String,
+    max_response_bytes: Option<u64>,
+    method: HttpMethod,
+    headers: Vec<HttpHeader>,
+    body: Option<Vec<u8>>,
+    transform: Option<TransformContext>,
+    is_replicated: Option<bool>,
+}
+
+fn log_outcall_errors(result: CallResult<(HttpResponse,)>) -> CallResult<(HttpResponse,)> {
+    match &result {
+        Ok((response,)) if response.status != 200u8 => {
+            ic_cdk::api::print(format!("The http_request returned status {}", response.status))
+        }
+        Ok(_) => {}
+        Err((r, m)) => ic_cdk::api::print(format!(
+            "The http_request resulted into error. RejectionCode: {r:?}, Error: {m}"
+        )),
+    }
+    result
+}
+
+/// Makes outcalls through the management canister.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IcOutcallBackend;
+
+impl HttpOutcallBackend for IcOutcallBackend {
+    fn canister_id(&self) -> Principal {
+        ic_cdk::api::id()
+    }
+
+    fn http_request(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> BoxFuture<'static, CallResult<(HttpResponse,)>> {
+        http_request(request, cycles).map(log_outcall_errors).boxed()
+    }
+
+    fn http_request_non_replicated(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> BoxFuture<'static, CallResult<(HttpResponse,)>> {
+        let argument = NonReplicatedRequestArgument {
+            url: request.url,
+            max_response_bytes: request.max_response_bytes,
+            method: request.method,
+            headers: request.headers,
+            body: request.body,
+            transform: request.transform,
+            is_replicated: Some(false),
+        };
+        call_with_payment128(Principal::management_canister(), "http_request", (argument,), cycles)
+            .map(log_outcall_errors)
+            .boxed()
+    }
+}
+
+type Handler = dyn Fn(&CanisterHttpRequestArgument) -> HttpResponse + Send + Sync;
+type TransformFn = dyn Fn(TransformArgs) -> HttpResponse + Send + Sync;
+
+/// Emulates the management canister outside of the IC.
+///
+/// Responses come from `handler`, acting as the RPC server. Like on the IC, responses larger than
+/// `max_response_bytes` are rejected and the transform function named in the request is applied,
+/// looked up among the ones registered with [`NativeOutcallBackend::with_transform`].
+#[derive(Clone)]
+pub struct NativeOutcallBackend {
+    canister_id: Principal,
+    handler: Arc<Handler>,
+    transforms: HashMap<String, Arc<TransformFn>>,
+    requests: Arc<Mutex<Vec<(CanisterHttpRequestArgument, u128)>>>,
+}
+
+impl NativeOutcallBackend {
+    /// Creates new backend answering every outcall with `handler`.
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&CanisterHttpRequestArgument) -> HttpResponse + Send + Sync + 'static,
+    {
+        NativeOutcallBackend {
+            canister_id: Principal::anonymous(),
+            handler: Arc::new(handler),
+            transforms: HashMap::new(),
+            requests: Default::default(),
+        }
+    }
+
+    /// Principal of the emulated canister, the anonymous principal by default.
+    pub fn with_canister_id(self, canister_id: Principal) -> Self {
+        Self { canister_id, ..self }
+    }
+
+    /// Registers the query method `method` of the canister as a transform function.
+    pub fn with_transform<F>(mut self, method: &str, transform: F) -> Self
+    where
+        F: Fn(TransformArgs) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.transforms.insert(method.to_string(), Arc::new(transform));
+        self
+    }
+
+    /// Registers a transform function applying `processor`.
+    pub fn with_processor<P>(self, method: &str, processor: P) -> Self
+    where
+        P: TransformProcessor + Send + Sync + 'static,
+    {
+        self.with_transform(method, move |args| processor.transform(args))
+    }
+
+    /// Outcalls made so far and the cycles attached to them.
+    pub fn requests(&self) -> Vec<(CanisterHttpRequestArgument, u128)> {
+        self.requests.lock().clone()
+    }
+
+    fn respond(&self, request: &CanisterHttpRequestArgument) -> CallResult<(HttpResponse,)> {
+        let response = (self.handler)(request);
+        let max_response_bytes = request.max_response_bytes.unwrap_or(DEFAULT_MAX_RESPONSE_BYTES);
+        if response.body.len() as u64 > max_response_bytes {
+            return Err((
+                RejectionCode::SysFatal,
+                format!("Http body exceeds size limit of {} bytes.", max_response_bytes),
+            ));
+        }
+        let context = match &request.transform {
+            Some(context) => context,
+            None => return Ok((response,)),
+        };
+        let function = &context.function.0;
+        let transform = match self.transforms.get(&function.method) {
+            Some(transform) if function.principal == self.canister_id => transform,
+            _ => {
+                return Err((
+                    RejectionCode::CanisterError,
+                    format!("transform function {} is not registered", function.method),
+                ))
+            }
+        };
+        Ok((transform(TransformArgs {
+            response,
+            context: context.context.clone(),
+        }),))
+    }
+}
+
+impl fmt::Debug for NativeOutcallBackend {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("NativeOutcallBackend")
+            .field("canister_id", &self.canister_id)
+            .field("transforms", &self.transforms.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl HttpOutcallBackend for NativeOutcallBackend {
+    fn canister_id(&self) -> Principal {
+        self.canister_id
+    }
+
+    fn http_request(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> BoxFuture<'static, CallResult<(HttpResponse,)>> {
+        let result = self.respond(&request);
+        self.requests.lock().push((request, cycles));
+        future::ready(result).boxed()
+    }
+}
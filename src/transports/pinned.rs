@@ -0,0 +1,327 @@
+//! Block-pinned Transport
+
+use crate::{
+    error::{Error, Result},
+    helpers,
+    metering::{Charging, CycleCost},
+    rpc,
+    types::U64,
+    BatchTransport, RequestId, Transport,
+};
+use futures::future::{self, BoxFuture, FutureExt};
+use serde_json::Value;
+
+use super::ic_http_client::CallOptions;
+
+/// Block tags rewritten to the pinned block. `pending` and `earliest` are left alone since they
+/// don't stand for the chain head.
+const HEAD_TAGS: &[&str] = &["latest", "safe", "finalized"];
+
+/// Head a [`Pinned`] session resolves once and reads at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Head {
+    /// The latest block minus this many confirmations.
+    Confirmations(u64),
+    /// The latest block the consensus layer considers safe from reorgs.
+    Safe,
+    /// The latest finalized block.
+    Finalized,
+}
+
+/// Position of the block parameter of state-reading methods.
+fn block_param(method: &str) -> Option<usize> {
+    match method {
+        "eth_getBlockByNumber"
+        | "eth_getBlockTransactionCountByNumber"
+        | "eth_getUncleCountByBlockNumber"
+        | "eth_getTransactionByBlockNumberAndIndex"
+        | "eth_getUncleByBlockNumberAndIndex" => Some(0),
+        "eth_getBalance"
+        | "eth_getCode"
+        | "eth_getTransactionCount"
+        | "eth_call"
+        | "eth_estimateGas"
+        | "eth_feeHistory" => Some(1),
+        "eth_getStorageAt" | "eth_getProof" => Some(2),
+        _ => None,
+    }
+}
+
+/// Transport wrapper reading every state at the same block.
+///
+/// Block parameters of state-reading methods given as `latest`, `safe` or `finalized`, or left out,
+/// are replaced with the pinned block number, as are the bounds of `eth_getLogs` filters not
+/// selecting a block hash. `eth_blockNumber` is answered with the pinned block without a request.
+/// Explicit block numbers and hashes are kept.
+#[derive(Debug, Clone)]
+pub struct Pinned<T> {
+    transport: T,
+    block: U64,
+}
+
+impl<T: Transport> Pinned<T> {
+    /// Reads through `transport` at `block`.
+    pub fn new(transport: T, block: U64) -> Self {
+        Pinned { transport, block }
+    }
+
+    /// The block every read is pinned to.
+    pub fn block(&self) -> U64 {
+        self.block
+    }
+
+    /// Borrows the inner transport.
+    pub fn inner(&self) -> &T {
+        &self.transport
+    }
+
+    fn pin(&self, call: &mut rpc::Call) {
+        let call = match call {
+            rpc::Call::MethodCall(call) => call,
+            _ => return,
+        };
+        let block = helpers::serialize(&self.block);
+        let params = match &mut call.params {
+            rpc::Params::Array(params) => params,
+            _ => return,
+        };
+        if call.method == "eth_getLogs" {
+            if let Some(Value::Object(filter)) = params.get_mut(0) {
+                if !filter.contains_key("blockHash") {
+                    for bound in ["fromBlock", "toBlock"] {
+                        let tag = filter.get(bound).map_or(Some("latest"), Value::as_str);
+                        if tag.is_some_and(|tag| HEAD_TAGS.contains(&tag)) {
+                            filter.insert(bound.to_string(), block.clone());
+                        }
+                    }
+                }
+            }
+            return;
+        }
+        match block_param(&call.method) {
+            Some(index) if index == params.len() && index > 0 => params.push(block),
+            Some(index)
+                if params
+                    .get(index)
+                    .and_then(Value::as_str)
+                    .is_some_and(|tag| HEAD_TAGS.contains(&tag)) =>
+            {
+                params[index] = block;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_block_number(call: &rpc::Call) -> bool {
+    matches!(call, rpc::Call::MethodCall(call) if call.method == "eth_blockNumber")
+}
+
+// `eth_blockNumber` is answered without a request and costs nothing.
+impl<T: Transport + CycleCost> CycleCost for Pinned<T> {
+    fn cycle_cost(&self, request: &rpc::Request, options: &CallOptions) -> u128 {
+        let request = match request {
+            rpc::Request::Single(call) if is_block_number(call) => return 0,
+            rpc::Request::Single(call) => {
+                let mut call = call.clone();
+                self.pin(&mut call);
+                rpc::Request::Single(call)
+            }
+            rpc::Request::Batch(calls) => {
+                let remote: Vec<_> = calls
+                    .iter()
+                    .filter(|call| !is_block_number(call))
+                    .map(|call| {
+                        let mut call = call.clone();
+                        self.pin(&mut call);
+                        call
+                    })
+                    .collect();
+                if remote.is_empty() {
+                    return 0;
+                }
+                rpc::Request::Batch(remote)
+            }
+        };
+        self.transport.cycle_cost(&request, options)
+    }
+}
+
+impl<T: Charging> Charging for Pinned<T> {}
+
+impl<T> Transport for Pinned<T>
+where
+    T: Transport,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.transport.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, mut request: rpc::Call, options: CallOptions) -> Self::Out {
+        if is_block_number(&request) {
+            return future::ok(helpers::serialize(&self.block)).boxed();
+        }
+        self.pin(&mut request);
+        self.transport.send(id, request, options).boxed()
+    }
+
+    fn set_max_response_bytes(&mut self, v: u64) {
+        self.transport.set_max_response_bytes(v);
+    }
+}
+
+impl<T> BatchTransport for Pinned<T>
+where
+    T: BatchTransport,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, Result<Vec<Result<rpc::Value>>>>;
+
+    fn send_batch<I>(&self, requests: I, options: CallOptions) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let requests: Vec<_> = requests
+            .into_iter()
+            .map(|(id, mut call)| {
+                self.pin(&mut call);
+                (id, call)
+            })
+            .collect();
+        let local: Vec<_> = requests.iter().map(|(_, call)| is_block_number(call)).collect();
+        let remote: Vec<_> = requests
+            .into_iter()
+            .filter(|(_, call)| !is_block_number(call))
+            .collect();
+        let block = helpers::serialize(&self.block);
+        let batch = if remote.is_empty() {
+            future::ok(vec![]).boxed()
+        } else {
+            self.transport.send_batch(remote, options).boxed()
+        };
+        batch
+            .map(move |results| {
+                let mut results = results?.into_iter();
+                Ok(local
+                    .iter()
+                    .map(|local| match local {
+                        true => Ok(block.clone()),
+                        false => results
+                            .next()
+                            .unwrap_or_else(|| Err(Error::InvalidResponse("missing batch response".into()))),
+                    })
+                    .collect())
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        transports::{
+            replay::{Interaction, Response},
+            Replay,
+        },
+        types::{Address, Block, BlockNumber, CallRequest, FilterBuilder, H256},
+        Web3,
+    };
+    use serde_json::json;
+
+    fn interaction(method: &str, params: Value, result: Value) -> Interaction {
+        Interaction {
+            method: method.into(),
+            params,
+            response: Response::Result(result),
+        }
+    }
+
+    fn node() -> Replay {
+        let address = "0x0000000000000000000000000000000000000001";
+        Replay::new(vec![
+            interaction("eth_blockNumber", json!([]), json!("0x64")),
+            interaction("eth_getBalance", json!([address, "0x5e"]), json!("0x1")),
+            interaction("eth_getBalance", json!([address, "0x10"]), json!("0x2")),
+            interaction("eth_call", json!([{"to": address}, "0x5e"]), json!("0x")),
+            interaction(
+                "eth_getLogs",
+                json!([{"fromBlock": "0x5e", "toBlock": "0x5e"}]),
+                json!([]),
+            ),
+            interaction(
+                "eth_getBlockByNumber",
+                json!(["finalized", false]),
+                serde_json::to_value(Block::<H256> {
+                    number: Some(0x40.into()),
+                    ..Default::default()
+                })
+                .unwrap(),
+            ),
+        ])
+    }
+
+    #[test]
+    fn should_pin_state_reads_to_the_resolved_head() {
+        let web3 =
+            futures::executor::block_on(Web3::new(node()).pinned(Head::Confirmations(6), CallOptions::default()))
+                .unwrap();
+        let eth = web3.eth();
+        let address = Address::from_low_u64_be(1);
+
+        let block_number = futures::executor::block_on(eth.block_number(CallOptions::default()));
+        let latest = futures::executor::block_on(eth.balance(address, None, CallOptions::default()));
+        let explicit = futures::executor::block_on(eth.balance(
+            address,
+            Some(BlockNumber::Number(0x10.into())),
+            CallOptions::default(),
+        ));
+        let call = futures::executor::block_on(eth.call(
+            CallRequest {
+                to: Some(address),
+                ..Default::default()
+            },
+            None,
+            CallOptions::default(),
+        ));
+        let logs = futures::executor::block_on(eth.logs(FilterBuilder::default().build(), CallOptions::default()));
+
+        assert_eq!(web3.transport().block(), U64::from(0x5e));
+        assert_eq!(block_number, Ok(U64::from(0x5e)));
+        assert_eq!(latest, Ok(1.into()));
+        assert_eq!(explicit, Ok(2.into()));
+        assert!(call.is_ok());
+        assert_eq!(logs, Ok(vec![]));
+    }
+
+    #[test]
+    fn should_resolve_finalized_heads() {
+        let web3 =
+            futures::executor::block_on(Web3::new(node()).pinned(Head::Finalized, CallOptions::default())).unwrap();
+
+        assert_eq!(web3.transport().block(), U64::from(0x40));
+    }
+
+    #[test]
+    fn should_answer_block_numbers_within_batches() {
+        let transport = Pinned::new(node(), 0x5e.into());
+        let address = json!("0x0000000000000000000000000000000000000001");
+
+        let results = futures::executor::block_on(transport.send_batch(
+            vec![
+                (
+                    0,
+                    helpers::build_request(0, "eth_getBalance", vec![address, json!("latest")]),
+                ),
+                (1, helpers::build_request(1, "eth_blockNumber", vec![])),
+            ],
+            CallOptions::default(),
+        ));
+
+        assert_eq!(results, Ok(vec![Ok(json!("0x1")), Ok(json!("0x5e"))]));
+    }
+}
@@ -0,0 +1,351 @@
+//! Quorum Transport
+
+use crate::{
+    error::{Error, ProviderAnswer, Result, TransportError},
+    helpers,
+    metering::{Charging, CycleCost},
+    rpc, BatchTransport, RequestId, Transport,
+};
+use futures::{
+    future::{self, BoxFuture},
+    stream::{FuturesUnordered, StreamExt},
+    FutureExt,
+};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use super::{ic_http_client::CallOptions, ICHttp};
+
+type RpcResult = Result<rpc::Value>;
+
+/// Transport sending every call to several providers and only resolving when enough of them agree.
+///
+/// Each call is dispatched to `fanout` endpoints (all of them by default), rotating the starting
+/// endpoint between calls. The call resolves as soon as `quorum` endpoints returned identical
+/// decoded results. Otherwise it fails with [`Error::InconsistentProviders`] listing every answer.
+#[derive(Debug, Clone)]
+pub struct Quorum<T> {
+    endpoints: Vec<(String, T)>,
+    quorum: usize,
+    fanout: usize,
+    id: Arc<AtomicUsize>,
+}
+
+impl<T: Transport> Quorum<T> {
+    /// Creates new quorum transport given labelled endpoints and the number of matching answers required.
+    pub fn new<I, S>(endpoints: I, quorum: usize) -> Result<Self>
+    where
+        I: IntoIterator<Item = (S, T)>,
+        S: Into<String>,
+    {
+        let endpoints: Vec<_> = endpoints
+            .into_iter()
+            .map(|(label, transport)| (label.into(), transport))
+            .collect();
+        let fanout = endpoints.len();
+        Self {
+            endpoints,
+            quorum,
+            fanout,
+            id: Default::default(),
+        }
+        .validated()
+    }
+
+    /// Only send each call to `fanout` of the endpoints instead of all of them.
+    pub fn with_fanout(self, fanout: usize) -> Result<Self> {
+        Self { fanout, ..self }.validated()
+    }
+
+    /// Number of matching answers required to resolve a call.
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    /// Number of endpoints each call is sent to.
+    pub fn fanout(&self) -> usize {
+        self.fanout
+    }
+
+    fn validated(self) -> Result<Self> {
+        if self.quorum == 0 || self.fanout > self.endpoints.len() || self.quorum > self.fanout {
+            return Err(Error::Transport(TransportError::Message(format!(
+                "invalid quorum configuration: {}-of-{} with {} endpoints",
+                self.quorum,
+                self.fanout,
+                self.endpoints.len()
+            ))));
+        }
+        Ok(self)
+    }
+
+    fn next_id(&self) -> RequestId {
+        self.id.fetch_add(1, Ordering::AcqRel)
+    }
+
+    /// Endpoints a given request is dispatched to.
+    fn selected(&self, id: RequestId) -> impl Iterator<Item = &(String, T)> {
+        let start = id % self.endpoints.len();
+        self.endpoints.iter().cycle().skip(start).take(self.fanout)
+    }
+}
+
+impl Quorum<ICHttp> {
+    /// Creates new quorum transport with one `ICHttp` endpoint per URL.
+    pub fn from_urls(urls: &[&str], max_resp: Option<u64>, quorum: usize) -> Result<Self> {
+        let endpoints = urls
+            .iter()
+            .map(|url| Ok((url.to_string(), ICHttp::new(url, max_resp)?)))
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(endpoints, quorum)
+    }
+}
+
+// Every call is sent to `fanout` endpoints, charged at the most expensive one.
+impl<T: CycleCost> CycleCost for Quorum<T> {
+    fn cycle_cost(&self, request: &rpc::Request, options: &CallOptions) -> u128 {
+        let cost = self
+            .endpoints
+            .iter()
+            .map(|(_, transport)| transport.cycle_cost(request, options))
+            .max()
+            .unwrap_or(0);
+        cost.saturating_mul(self.fanout as u128)
+    }
+}
+
+impl<T: Charging> Charging for Quorum<T> {}
+
+impl<T> Transport for Quorum<T>
+where
+    T: Transport,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, RpcResult>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.next_id();
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call, options: CallOptions) -> Self::Out {
+        let mut pending = self
+            .selected(id)
+            .map(|(label, transport)| {
+                let label = label.clone();
+                transport
+                    .send(id, request.clone(), options.clone())
+                    .map(move |result| (label, result))
+            })
+            .collect::<FuturesUnordered<_>>();
+        let quorum = self.quorum;
+
+        Box::pin(async move {
+            let mut answers = Vec::with_capacity(pending.len());
+            while let Some(answer) = pending.next().await {
+                answers.push(answer);
+                if let Some(result) = agreed(&answers, quorum) {
+                    return result;
+                }
+                if best_agreement(&answers) + pending.len() < quorum {
+                    break;
+                }
+            }
+            Err(inconsistent(answers))
+        })
+    }
+
+    fn set_max_response_bytes(&mut self, v: u64) {
+        for (_, transport) in self.endpoints.iter_mut() {
+            transport.set_max_response_bytes(v);
+        }
+    }
+}
+
+impl<T> BatchTransport for Quorum<T>
+where
+    T: BatchTransport,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, Result<Vec<RpcResult>>>;
+
+    fn send_batch<I>(&self, requests: I, options: CallOptions) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let requests: Vec<_> = requests.into_iter().collect();
+        let id = requests.first().map(|(id, _)| *id).unwrap_or_else(|| self.next_id());
+        let len = requests.len();
+        let batches = self
+            .selected(id)
+            .map(|(label, transport)| {
+                let label = label.clone();
+                transport
+                    .send_batch(requests.clone(), options.clone())
+                    .map(move |results| (label, results))
+            })
+            .collect::<Vec<_>>();
+        let quorum = self.quorum;
+
+        Box::pin(async move {
+            let mut answers: Vec<Vec<(String, RpcResult)>> = vec![Vec::new(); len];
+            for (label, results) in future::join_all(batches).await {
+                let results = match results {
+                    Ok(results) if results.len() == len => results,
+                    Ok(_) => vec![Err(Error::InvalidResponse("unexpected number of responses".into())); len],
+                    Err(err) => vec![Err(err); len],
+                };
+                for (answer, result) in answers.iter_mut().zip(results) {
+                    answer.push((label.clone(), result));
+                }
+            }
+            Ok(answers
+                .into_iter()
+                .map(|answers| agreed(&answers, quorum).unwrap_or_else(|| Err(inconsistent(answers))))
+                .collect())
+        })
+    }
+}
+
+/// Two answers match when they carry the same value or the same JSON-RPC error.
+fn same_answer(a: &RpcResult, b: &RpcResult) -> bool {
+    match (a, b) {
+        (Ok(a), Ok(b)) => a == b,
+        (Err(Error::Rpc(a)), Err(Error::Rpc(b))) => a == b,
+        _ => false,
+    }
+}
+
+fn agreement(answers: &[(String, RpcResult)], answer: &RpcResult) -> usize {
+    answers.iter().filter(|(_, other)| same_answer(answer, other)).count()
+}
+
+fn best_agreement(answers: &[(String, RpcResult)]) -> usize {
+    answers
+        .iter()
+        .map(|(_, answer)| agreement(answers, answer))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Returns the answer given by at least `quorum` endpoints, if any.
+fn agreed(answers: &[(String, RpcResult)], quorum: usize) -> Option<RpcResult> {
+    answers
+        .iter()
+        .map(|(_, answer)| answer)
+        .find(|answer| agreement(answers, answer) >= quorum)
+        .cloned()
+}
+
+fn inconsistent(answers: Vec<(String, RpcResult)>) -> Error {
+    Error::InconsistentProviders(
+        answers
+            .into_iter()
+            .map(|(endpoint, answer)| ProviderAnswer {
+                endpoint,
+                answer: answer.map_err(|err| err.to_string()),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transports::test::TestTransport;
+
+    fn endpoint(label: &str, response: Option<rpc::Value>) -> (String, TestTransport) {
+        let mut transport = TestTransport::default();
+        if let Some(response) = response {
+            transport.add_response(response);
+        }
+        (label.to_string(), transport)
+    }
+
+    #[test]
+    fn should_resolve_when_quorum_agrees() {
+        let quorum = Quorum::new(
+            vec![
+                endpoint("a", Some("0x1".into())),
+                endpoint("b", Some("0x2".into())),
+                endpoint("c", Some("0x1".into())),
+            ],
+            2,
+        )
+        .unwrap();
+
+        let result = futures::executor::block_on(quorum.execute("eth_blockNumber", vec![], CallOptions::default()));
+
+        assert_eq!(result, Ok("0x1".into()));
+    }
+
+    #[test]
+    fn should_list_every_answer_when_providers_disagree() {
+        let quorum = Quorum::new(
+            vec![
+                endpoint("a", Some("0x1".into())),
+                endpoint("b", Some("0x2".into())),
+                endpoint("c", None),
+            ],
+            2,
+        )
+        .unwrap();
+
+        let result = futures::executor::block_on(quorum.execute("eth_blockNumber", vec![], CallOptions::default()));
+
+        match result {
+            Err(Error::InconsistentProviders(mut answers)) => {
+                answers.sort_by(|a, b| a.endpoint.cmp(&b.endpoint));
+                assert_eq!(
+                    answers,
+                    vec![
+                        ProviderAnswer {
+                            endpoint: "a".into(),
+                            answer: Ok("0x1".into()),
+                        },
+                        ProviderAnswer {
+                            endpoint: "b".into(),
+                            answer: Ok("0x2".into()),
+                        },
+                        ProviderAnswer {
+                            endpoint: "c".into(),
+                            answer: Err(Error::Unreachable.to_string()),
+                        },
+                    ]
+                );
+            }
+            other => panic!("expected inconsistent providers, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_only_query_the_configured_subset() {
+        let quorum = Quorum::new(
+            vec![
+                endpoint("a", Some("0x1".into())),
+                endpoint("b", Some("0x1".into())),
+                endpoint("c", None),
+            ],
+            2,
+        )
+        .unwrap()
+        .with_fanout(2)
+        .unwrap();
+
+        let result = futures::executor::block_on(quorum.execute("eth_blockNumber", vec![], CallOptions::default()));
+
+        assert_eq!(result, Ok("0x1".into()));
+    }
+
+    #[test]
+    fn should_reject_unreachable_quorum() {
+        let endpoints = vec![endpoint("a", None), endpoint("b", None)];
+
+        assert!(Quorum::new(endpoints.clone(), 3).is_err());
+        assert!(Quorum::new(endpoints.clone(), 0).is_err());
+        assert!(Quorum::new(endpoints, 2).unwrap().with_fanout(1).is_err());
+    }
+}
@@ -0,0 +1,329 @@
+//! Record-and-replay Transports for fixture based tests.
+
+use crate::{
+    error::{Error, Result},
+    helpers,
+    metering::{Charging, CycleCost},
+    rpc, BatchTransport, RequestId, Transport,
+};
+use futures::future::{self, BoxFuture, FutureExt};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use super::ic_http_client::CallOptions;
+
+/// Answer of the node to a recorded call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Response {
+    Result(rpc::Value),
+    Error(rpc::Error),
+}
+
+/// A recorded call and the answer it got.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub params: rpc::Value,
+    pub response: Response,
+}
+
+/// Transport wrapper recording every call and its answer, to be saved as a JSON fixture for [`Replay`].
+///
+/// Only results and JSON-RPC errors are recorded, transport failures are passed through untouched.
+#[derive(Debug, Clone)]
+pub struct Recorder<T> {
+    transport: T,
+    interactions: Arc<Mutex<Vec<Interaction>>>,
+}
+
+impl<T> Recorder<T> {
+    /// Creates new recorder wrapping `transport`.
+    pub fn new(transport: T) -> Self {
+        Recorder {
+            transport,
+            interactions: Default::default(),
+        }
+    }
+
+    /// The interactions recorded so far.
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.interactions.lock().clone()
+    }
+
+    /// The interactions recorded so far as a JSON fixture.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&*self.interactions.lock()).expect("interactions are serializable; qed")
+    }
+
+    /// Writes the interactions recorded so far to a JSON fixture file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_json())?;
+        Ok(())
+    }
+}
+
+fn record(interactions: &Mutex<Vec<Interaction>>, (method, params): (String, rpc::Value), result: &Result<rpc::Value>) {
+    let response = match result {
+        Ok(value) => Response::Result(value.clone()),
+        Err(Error::Rpc(err)) => Response::Error(err.clone()),
+        Err(_) => return,
+    };
+    interactions.lock().push(Interaction {
+        method,
+        params,
+        response,
+    });
+}
+
+impl<T: CycleCost> CycleCost for Recorder<T> {
+    fn cycle_cost(&self, request: &rpc::Request, options: &CallOptions) -> u128 {
+        self.transport.cycle_cost(request, options)
+    }
+}
+
+impl<T: Charging> Charging for Recorder<T> {}
+
+impl<T> Transport for Recorder<T>
+where
+    T: Transport,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.transport.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call, options: CallOptions) -> Self::Out {
+        let key = helpers::method_and_params(&request);
+        let interactions = self.interactions.clone();
+        self.transport
+            .send(id, request, options)
+            .map(move |result| {
+                record(&interactions, key, &result);
+                result
+            })
+            .boxed()
+    }
+
+    fn set_max_response_bytes(&mut self, v: u64) {
+        self.transport.set_max_response_bytes(v);
+    }
+}
+
+impl<T> BatchTransport for Recorder<T>
+where
+    T: BatchTransport,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, Result<Vec<Result<rpc::Value>>>>;
+
+    fn send_batch<I>(&self, requests: I, options: CallOptions) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let requests: Vec<_> = requests.into_iter().collect();
+        let keys: Vec<_> = requests
+            .iter()
+            .map(|(_, call)| helpers::method_and_params(call))
+            .collect();
+        let interactions = self.interactions.clone();
+        self.transport
+            .send_batch(requests, options)
+            .map(move |results| {
+                if let Ok(results) = &results {
+                    for (key, result) in keys.into_iter().zip(results) {
+                        record(&interactions, key, result);
+                    }
+                }
+                results
+            })
+            .boxed()
+    }
+}
+
+/// Transport answering calls from a JSON fixture written by [`Recorder`].
+///
+/// Calls are matched by method and params rather than by order. Calls recorded several times are
+/// answered in recorded order, the last answer is repeated once they are used up. A call missing
+/// from the fixture panics, naming the call, so that tests fail loudly instead of seeing an error
+/// the code under test may swallow.
+#[derive(Debug, Clone, Default)]
+pub struct Replay {
+    interactions: Arc<Mutex<Vec<(Interaction, bool)>>>,
+    id: Arc<AtomicUsize>,
+}
+
+impl Replay {
+    /// Creates new replay transport answering with `interactions`.
+    pub fn new(interactions: Vec<Interaction>) -> Self {
+        Replay {
+            interactions: Arc::new(Mutex::new(
+                interactions
+                    .into_iter()
+                    .map(|interaction| (interaction, false))
+                    .collect(),
+            )),
+            id: Default::default(),
+        }
+    }
+
+    /// Creates new replay transport from a JSON fixture.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(Self::new(serde_json::from_str(json)?))
+    }
+
+    /// Creates new replay transport from a JSON fixture file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Interactions of the fixture no call matched so far.
+    pub fn unused(&self) -> Vec<Interaction> {
+        self.interactions
+            .lock()
+            .iter()
+            .filter(|(_, used)| !used)
+            .map(|(interaction, _)| interaction.clone())
+            .collect()
+    }
+
+    fn answer(&self, call: &rpc::Call) -> Result<rpc::Value> {
+        let (method, params) = helpers::method_and_params(call);
+        let mut interactions = self.interactions.lock();
+        let matches = |interaction: &Interaction| interaction.method == method && interaction.params == params;
+        let position = interactions
+            .iter()
+            .position(|(interaction, used)| !used && matches(interaction))
+            .or_else(|| interactions.iter().rposition(|(interaction, _)| matches(interaction)))
+            .unwrap_or_else(|| panic!("Unexpected call to {} with params {}", method, params));
+        let (interaction, used) = &mut interactions[position];
+        *used = true;
+        match &interaction.response {
+            Response::Result(value) => Ok(value.clone()),
+            Response::Error(err) => Err(Error::Rpc(err.clone())),
+        }
+    }
+
+    fn next_id(&self) -> RequestId {
+        self.id.fetch_add(1, Ordering::AcqRel)
+    }
+}
+
+impl Transport for Replay {
+    type Out = BoxFuture<'static, Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.next_id();
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call, _options: CallOptions) -> Self::Out {
+        future::ready(self.answer(&request)).boxed()
+    }
+}
+
+impl BatchTransport for Replay {
+    type Batch = BoxFuture<'static, Result<Vec<Result<rpc::Value>>>>;
+
+    fn send_batch<I>(&self, requests: I, _options: CallOptions) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let results = requests.into_iter().map(|(_, call)| self.answer(&call)).collect();
+        future::ok(results).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transports::NativeOutcallBackend, types::U64, Web3};
+    use ic_cdk::api::management_canister::http_request::{CanisterHttpRequestArgument, HttpResponse, TransformArgs};
+
+    fn node(request: &CanisterHttpRequestArgument) -> HttpResponse {
+        let call: serde_json::Value = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+        let body = match call["method"].as_str() {
+            Some("eth_blockNumber") => serde_json::json!({"jsonrpc": "2.0", "id": call["id"], "result": "0x10"}),
+            _ => serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": call["id"],
+                "error": {"code": -32601, "message": "method not found"}
+            }),
+        };
+        HttpResponse {
+            status: 200u8.into(),
+            headers: vec![],
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    fn recorded() -> String {
+        let backend = NativeOutcallBackend::new(node).with_transform("transform", |args: TransformArgs| args.response);
+        let recorder = Recorder::new(
+            crate::transports::ICHttp::new("https://rpc.example.com", None)
+                .unwrap()
+                .with_backend(backend),
+        );
+        let web3 = Web3::new(recorder.clone());
+        futures::executor::block_on(async {
+            web3.eth().block_number(CallOptions::default()).await.unwrap();
+            web3.eth().chain_id(CallOptions::default()).await.unwrap_err();
+        });
+        recorder.to_json()
+    }
+
+    #[test]
+    fn should_replay_recorded_interactions() {
+        let replay = Replay::from_json(&recorded()).unwrap();
+        let web3 = Web3::new(replay.clone());
+
+        // Calls are matched by method and params, not by order.
+        let chain_id = futures::executor::block_on(web3.eth().chain_id(CallOptions::default()));
+        let block_number = futures::executor::block_on(web3.eth().block_number(CallOptions::default()));
+
+        assert_eq!(block_number, Ok(U64::from(16)));
+        assert_eq!(
+            chain_id,
+            Err(Error::Rpc(rpc::Error {
+                code: rpc::ErrorCode::MethodNotFound,
+                message: "method not found".into(),
+                data: None,
+            }))
+        );
+        assert!(replay.unused().is_empty());
+    }
+
+    #[test]
+    fn should_answer_repeated_calls_in_recorded_order() {
+        let call = |result: &str| Interaction {
+            method: "eth_blockNumber".into(),
+            params: serde_json::json!([]),
+            response: Response::Result(result.into()),
+        };
+        let replay = Replay::new(vec![call("0x1"), call("0x2")]);
+
+        let results = (0..3)
+            .map(|_| futures::executor::block_on(replay.execute("eth_blockNumber", vec![], CallOptions::default())))
+            .collect::<Vec<_>>();
+
+        assert_eq!(results, vec![Ok("0x1".into()), Ok("0x2".into()), Ok("0x2".into())]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected call to eth_gasPrice")]
+    fn should_panic_on_unexpected_calls() {
+        let replay = Replay::from_json(&recorded()).unwrap();
+
+        let _ = futures::executor::block_on(replay.execute("eth_gasPrice", vec![], CallOptions::default()));
+    }
+}
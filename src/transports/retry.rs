@@ -0,0 +1,285 @@
+//! Retry policy for transient outcall failures.
+
+#[cfg(target_arch = "wasm32")]
+use crate::helpers;
+use crate::{
+    error::{Error, Result, TransportError},
+    transforms::transform::HTTP_ERROR_CODE,
+};
+use derive_builder::Builder;
+use futures::Future;
+use ic_cdk::api::call::RejectionCode;
+#[cfg(target_arch = "wasm32")]
+use ic_cdk::api::management_canister::main::raw_rand;
+use serde_json::Value;
+use std::time::Duration;
+
+use super::ic_http_client::CallOptions;
+
+/// Largest response an HTTPS outcall may return.
+pub const MAX_RESPONSE_BYTES_LIMIT: u64 = 2_000_000;
+
+/// Which failures are retried, how often and how long to wait in between.
+///
+/// Delays double after every attempt, starting at `initial_backoff` and capped at `max_backoff`.
+/// Natively they are skipped.
+///
+/// Inside a canister the call context has to stay open while waiting, or the IC would end the
+/// message execution and reject the update call for not replying. Delays are therefore spent
+/// awaiting `raw_rand` calls to the management canister, one round of consensus each, so they are
+/// rounded up to whole rounds and cost the fee of an inter-canister call per round. Queries can't
+/// make these calls, so retry with a zero `initial_backoff` there, or not at all.
+#[derive(Builder, Clone, Debug, PartialEq, Eq)]
+#[builder(default)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between two attempts.
+    pub max_backoff: Duration,
+    /// IC rejection codes worth retrying.
+    pub retryable_rejection_codes: Vec<RejectionCode>,
+    /// HTTP statuses worth retrying, whether the outcall failed with them or a transform turned them into
+    /// an error envelope.
+    pub retryable_http_statuses: Vec<u16>,
+    /// JSON-RPC error codes worth retrying.
+    pub retryable_rpc_codes: Vec<i64>,
+    /// Double `max_response_bytes` and retry when the response exceeded it, up to `max_response_bytes_limit`.
+    pub grow_max_response: bool,
+    /// Largest `max_response_bytes` retries may grow to.
+    pub max_response_bytes_limit: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(30),
+            retryable_rejection_codes: vec![RejectionCode::SysTransient],
+            retryable_http_statuses: vec![429, 500, 502, 503, 504],
+            // Limit exceeded and internal error.
+            retryable_rpc_codes: vec![-32005, -32603],
+            grow_max_response: true,
+            max_response_bytes_limit: MAX_RESPONSE_BYTES_LIMIT,
+        }
+    }
+}
+
+/// What to do after a failed attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Give up and return the error.
+    Fail,
+    /// Wait and try again.
+    Retry,
+    /// Try again straight away with a larger response size limit.
+    GrowResponse,
+}
+
+impl RetryPolicy {
+    /// Policy making a single attempt.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Classifies a failure.
+    pub fn decide(&self, err: &Error) -> RetryDecision {
+        match err {
+            Error::Transport(TransportError::Rejected(_, message))
+                if self.grow_max_response && is_response_too_large(message) =>
+            {
+                RetryDecision::GrowResponse
+            }
+            Error::Transport(TransportError::Rejected(code, _)) if self.retryable_rejection_codes.contains(code) => {
+                RetryDecision::Retry
+            }
+            Error::Transport(TransportError::Code(status)) if self.retryable_http_statuses.contains(status) => {
+                RetryDecision::Retry
+            }
+            Error::Rpc(err) if err.code.code() == HTTP_ERROR_CODE => match err.data.as_ref().and_then(Value::as_u64) {
+                Some(status) if self.retryable_http_statuses.iter().any(|s| u64::from(*s) == status) => {
+                    RetryDecision::Retry
+                }
+                _ => RetryDecision::Fail,
+            },
+            Error::Rpc(err) if self.retryable_rpc_codes.contains(&err.code.code()) => RetryDecision::Retry,
+            _ => RetryDecision::Fail,
+        }
+    }
+
+    /// Delay before the given retry, counting from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff))
+    }
+
+    /// Runs `attempt` until it succeeds, fails with an error that isn't retryable or runs out of attempts.
+    ///
+    /// `max_resp` is the response size limit applied when `options` don't set one.
+    pub async fn run<F, Fut, R>(&self, mut options: CallOptions, max_resp: u64, attempt: F) -> Result<R>
+    where
+        F: Fn(CallOptions) -> Fut,
+        Fut: Future<Output = Result<R>>,
+    {
+        let mut retries = 0;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let err = match attempt(options.clone()).await {
+                Ok(result) => return Ok(result),
+                Err(err) => err,
+            };
+            if attempts >= self.max_attempts {
+                return Err(err);
+            }
+            match self.decide(&err) {
+                RetryDecision::Fail => return Err(err),
+                RetryDecision::GrowResponse => {
+                    let current = options.max_resp().unwrap_or(max_resp);
+                    if current >= self.max_response_bytes_limit {
+                        return Err(err);
+                    }
+                    options.set_max_resp(Some(current.saturating_mul(2).min(self.max_response_bytes_limit)));
+                }
+                RetryDecision::Retry => {
+                    retries += 1;
+                    sleep(self.backoff(retries)).await;
+                }
+            }
+        }
+    }
+}
+
+/// The IC rejects outcalls whose response is larger than `max_response_bytes`.
+fn is_response_too_large(message: &str) -> bool {
+    message.contains("size limit")
+}
+
+/// Waits for `delay` while keeping the call context open.
+///
+/// A pending future without outstanding calls would end the message execution, and a timer waking
+/// it up later would resume it in a context where the call can no longer be replied to.
+#[cfg(target_arch = "wasm32")]
+async fn sleep(delay: Duration) {
+    let deadline = helpers::now_nanos().saturating_add(delay.as_nanos().min(u64::MAX as u128) as u64);
+    while helpers::now_nanos() < deadline {
+        if raw_rand().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// There are no canister timers outside of the IC, retries happen straight away.
+#[cfg(not(target_arch = "wasm32"))]
+async fn sleep(_delay: Duration) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc;
+    use std::cell::RefCell;
+
+    fn rejected(code: RejectionCode, message: &str) -> Error {
+        Error::Transport(TransportError::Rejected(code, message.to_string()))
+    }
+
+    #[test]
+    fn should_classify_failures() {
+        let policy = RetryPolicy::default();
+
+        assert_eq!(
+            policy.decide(&rejected(RejectionCode::SysTransient, "timeout")),
+            RetryDecision::Retry
+        );
+        assert_eq!(
+            policy.decide(&rejected(
+                RejectionCode::SysFatal,
+                "Http body exceeds size limit of 2000 bytes."
+            )),
+            RetryDecision::GrowResponse
+        );
+        assert_eq!(
+            policy.decide(&rejected(RejectionCode::CanisterReject, "no consensus")),
+            RetryDecision::Fail
+        );
+        assert_eq!(
+            policy.decide(&Error::Transport(TransportError::Code(503))),
+            RetryDecision::Retry
+        );
+        assert_eq!(
+            policy.decide(&Error::Rpc(rpc::Error::new(rpc::ErrorCode::ServerError(-32005)))),
+            RetryDecision::Retry
+        );
+        assert_eq!(
+            policy.decide(&Error::Rpc(rpc::Error::invalid_params("bad"))),
+            RetryDecision::Fail
+        );
+    }
+
+    #[test]
+    fn should_back_off_exponentially() {
+        let policy = RetryPolicy::default();
+
+        assert_eq!(policy.backoff(1), Duration::from_secs(2));
+        assert_eq!(policy.backoff(2), Duration::from_secs(4));
+        assert_eq!(policy.backoff(10), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn should_retry_until_success() {
+        let attempts = RefCell::new(vec![]);
+        let policy = RetryPolicy::default();
+
+        let result = futures::executor::block_on(policy.run(CallOptions::default(), 1_000, |options| {
+            attempts.borrow_mut().push(options);
+            let attempt = attempts.borrow().len();
+            async move {
+                match attempt {
+                    1 => Err(rejected(RejectionCode::SysTransient, "timeout")),
+                    _ => Ok(attempt),
+                }
+            }
+        }));
+
+        assert_eq!(result, Ok(2));
+    }
+
+    #[test]
+    fn should_grow_max_response_bytes() {
+        let attempts = RefCell::new(vec![]);
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            max_response_bytes_limit: 3_000,
+            ..Default::default()
+        };
+
+        let result: Result<()> = futures::executor::block_on(policy.run(CallOptions::default(), 1_000, |options| {
+            attempts.borrow_mut().push(options.max_resp());
+            async { Err(rejected(RejectionCode::SysFatal, "Http body exceeds size limit")) }
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(*attempts.borrow(), vec![None, Some(2_000), Some(3_000)]);
+    }
+
+    #[test]
+    fn should_give_up_after_max_attempts() {
+        let attempts = RefCell::new(0);
+        let policy = RetryPolicy::default();
+
+        let result: Result<()> = futures::executor::block_on(policy.run(CallOptions::default(), 1_000, |_| {
+            *attempts.borrow_mut() += 1;
+            async { Err(rejected(RejectionCode::SysTransient, "timeout")) }
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(*attempts.borrow(), 3);
+    }
+}
@@ -134,6 +134,10 @@ pub enum BlockNumber {
     Earliest,
     /// Pending block (not yet part of the blockchain)
     Pending,
+    /// Latest block the consensus layer considers safe from reorgs
+    Safe,
+    /// Latest finalized block
+    Finalized,
     /// Block by number from canon chain
     Number(U64),
 }
@@ -154,6 +158,8 @@ impl Serialize for BlockNumber {
             BlockNumber::Latest => serializer.serialize_str("latest"),
             BlockNumber::Earliest => serializer.serialize_str("earliest"),
             BlockNumber::Pending => serializer.serialize_str("pending"),
+            BlockNumber::Safe => serializer.serialize_str("safe"),
+            BlockNumber::Finalized => serializer.serialize_str("finalized"),
         }
     }
 }
@@ -168,6 +174,8 @@ impl<'a> Deserialize<'a> for BlockNumber {
             "latest" => Ok(BlockNumber::Latest),
             "earliest" => Ok(BlockNumber::Earliest),
             "pending" => Ok(BlockNumber::Pending),
+            "safe" => Ok(BlockNumber::Safe),
+            "finalized" => Ok(BlockNumber::Finalized),
             _ if value.starts_with("0x") => U64::from_str_radix(&value[2..], 16)
                 .map(BlockNumber::Number)
                 .map_err(|e| D::Error::custom(format!("invalid block number: {}", e))),
@@ -330,6 +338,18 @@ mod tests {
         let deserialized = serde_json::from_value::<BlockNumber>(serialized).unwrap();
         assert_eq!(deserialized, BlockNumber::Pending);
 
+        // BlockNumber::Safe
+        let serialized = serde_json::to_value(BlockNumber::Safe).unwrap();
+        assert_eq!(serialized, "safe");
+        let deserialized = serde_json::from_value::<BlockNumber>(serialized).unwrap();
+        assert_eq!(deserialized, BlockNumber::Safe);
+
+        // BlockNumber::Finalized
+        let serialized = serde_json::to_value(BlockNumber::Finalized).unwrap();
+        assert_eq!(serialized, "finalized");
+        let deserialized = serde_json::from_value::<BlockNumber>(serialized).unwrap();
+        assert_eq!(deserialized, BlockNumber::Finalized);
+
         // BlockNumber::Number
         let serialized = serde_json::to_value(BlockNumber::Number(100.into())).unwrap();
         assert_eq!(serialized, "0x64");
@@ -1,4 +1,7 @@
-use crate::types::{SignedData, SignedTransaction, H256};
+use crate::{
+    signing::{self, RecoveryError},
+    types::{Address, SignedData, SignedTransaction, H256},
+};
 use std::{
     error::Error,
     fmt::{Display, Formatter, Result as FmtResult},
@@ -83,6 +86,19 @@ impl Recovery {
 
         Some((signature, recovery_id))
     }
+
+    /// Recover the address that signed the message.
+    ///
+    /// Data messages are enveloped according to EIP-191 before hashing, the way `personal_sign`
+    /// and Solidity's `ecrecover` of an `eth_sign` hash expect, while hashes are taken as they are.
+    pub fn recover_message(&self) -> Result<Address, RecoveryError> {
+        let message_hash = match self.message {
+            RecoveryMessage::Data(ref message) => signing::hash_message(message),
+            RecoveryMessage::Hash(hash) => hash,
+        };
+        let (signature, recovery_id) = self.as_signature().ok_or(RecoveryError::InvalidSignature)?;
+        signing::recover(message_hash.as_bytes(), &signature, recovery_id)
+    }
 }
 
 impl<'a> From<&'a SignedData> for Recovery {
@@ -191,4 +207,22 @@ mod tests {
             .unwrap();
         assert_eq!((sig.to_vec(), id), expected_signature);
     }
+
+    #[test]
+    fn recover_message() {
+        // test vector taken from:
+        // https://web3js.readthedocs.io/en/v1.2.2/web3-eth-accounts.html#recover
+        let r = hex!("b91467e570a6466aa9e9876cbcd013baba02900b8979d43fe208a4a4f339f5fd").into();
+        let s = hex!("6007e74cd82e037b800186422fc2da167c747ef045e5d18a5f5d4300f8e1a029").into();
+        let address = hex!("2c7536E3605D9C16a7a3D7b1898e529396a65c23").into();
+
+        assert_eq!(Recovery::new("Some data", 0x1c, r, s).recover_message(), Ok(address));
+        let message_hash: H256 = hex!("1da44b586eb0729ff70a73c326926f6ed5a25f5b056e7f47fbc6e58d86871655").into();
+        assert_eq!(Recovery::new(message_hash, 0x1c, r, s).recover_message(), Ok(address));
+        assert_ne!(Recovery::new("Other data", 0x1c, r, s).recover_message(), Ok(address));
+        assert_eq!(
+            Recovery::new("Some data", 0x1d, r, s).recover_message(),
+            Err(RecoveryError::InvalidSignature)
+        );
+    }
 }